@@ -0,0 +1,69 @@
+//! Decoder throughput, run with `cargo bench --bench decode`
+
+use std::{hint::black_box, time::Instant};
+
+use gb_rs::{
+    cpu::SizedInstruction,
+    memory::Memory,
+    utils::{Address, Byte},
+};
+
+const PROGRAM_SIZE: usize = 0x8000;
+const ROUNDS: usize = 50;
+
+/// Pseudo random program, so every opcode and operand shows up
+fn program() -> Vec<Byte> {
+    let mut state: u32 = 0x1234_5678;
+    (0..PROGRAM_SIZE)
+        .map(|_| {
+            // xorshift
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as Byte
+        })
+        .collect()
+}
+
+/// Decode the whole program instruction by instruction, returning how many were decoded
+fn decode_all(decode: impl Fn(Address) -> Option<SizedInstruction>) -> usize {
+    let mut count = 0;
+    let mut address: usize = 0;
+    while address < PROGRAM_SIZE - 2 {
+        let instruction = black_box(decode(address as Address));
+        address += instruction.map_or(1, |instruction| instruction.size as usize);
+        count += 1;
+    }
+    count
+}
+
+fn bench(name: &str, decode: impl Fn(Address) -> Option<SizedInstruction>) {
+    // warm up, which also builds the decode tables
+    decode_all(&decode);
+
+    let start = Instant::now();
+    let mut count = 0;
+    for _ in 0..ROUNDS {
+        count += decode_all(&decode);
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:12} {:8.2} ns/instruction {:8.1} M instructions/s",
+        name,
+        elapsed.as_nanos() as f64 / count as f64,
+        count as f64 / elapsed.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    let program = program();
+    bench("decode_with", |address| {
+        SizedInstruction::decode_with(|offset| program[(address + offset) as usize])
+    });
+
+    let mut memory = Memory::new();
+    memory.write_test(program.clone());
+    bench("decode", |address| {
+        SizedInstruction::decode(&memory, address)
+    });
+}
@@ -0,0 +1,503 @@
+use std::{collections::HashMap, fmt, iter::Peekable, str::Chars, sync::OnceLock};
+
+use crate::{
+    cpu::{Instruction, SizedInstruction, CB_PREFIX},
+    disasm::format_instruction,
+    utils::{Address, Byte, Word},
+};
+
+/// Register, condition and indirect operands, the way the disassembler writes them
+const REGISTERS: [&str; 21] = [
+    "a", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp", "nz", "z", "nc", "[hl]",
+    "[bc]", "[de]", "[hl+]", "[hl-]", "[c]",
+];
+
+/// Errors from assembling, with the line they occur on
+#[derive(Debug, PartialEq, Eq)]
+pub enum AsmError {
+    /// No opcode has this mnemonic and operands
+    InvalidInstruction(usize, String),
+    InvalidExpression(usize, String),
+    UnknownLabel(usize, String),
+    DuplicateLabel(usize, String),
+    /// An operand does not fit, e.g. a relative jump too far away
+    OutOfRange(usize, i64),
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidInstruction(line, text) => {
+                write!(f, "Line {}: invalid instruction {}", line, text)
+            }
+            Self::InvalidExpression(line, text) => {
+                write!(f, "Line {}: invalid expression {}", line, text)
+            }
+            Self::UnknownLabel(line, name) => write!(f, "Line {}: unknown label {}", line, name),
+            Self::DuplicateLabel(line, name) => {
+                write!(f, "Line {}: label {} is already defined", line, name)
+            }
+            Self::OutOfRange(line, value) => {
+                write!(f, "Line {}: operand {} out of range", line, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Immediate operand encoding of an opcode
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Immediate {
+    None,
+    Byte,
+    /// Signed offset of sp
+    Signed,
+    /// Low byte of an address in 0xFF00-0xFFFF
+    High,
+    /// Jump target relative to the next instruction
+    Relative,
+    Word,
+}
+
+impl Immediate {
+    fn of(instruction: Instruction) -> Self {
+        use Instruction::*;
+
+        match instruction {
+            LD_R_N(..) | LD_HL_N(_) | ADD_N(_) | SUB_N(_) | AND_N(_) | OR_N(_) | ADC_N(_)
+            | SBC_N(_) | XOR_N(_) | CP_N(_) => Self::Byte,
+            LD_HL_SP(_) | ADD_SP_E(_) => Self::Signed,
+            LDH_A_N(_) | LDH_N_A(_) => Self::High,
+            JR(_) | JR_CC(..) => Self::Relative,
+            LD_A_NN(_) | LD_NN_A(_) | LD_RR_NN(..) | LD_NN_SP(_) | JP_NN(_) | JP_CC_NN(..)
+            | CALL(_) | CALL_CC(..) => Self::Word,
+            _ => Self::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Register(String),
+    Value(String),
+    /// Address in brackets
+    Memory(String),
+    /// Signed offset in `sp + e`
+    SpOffset(String),
+}
+
+impl Operand {
+    fn parse(text: &str) -> Self {
+        let text = text.trim();
+        let compact = text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        let register = match compact.as_str() {
+            "[hli]" => "[hl+]",
+            "[hld]" => "[hl-]",
+            "[$ff00+c]" => "[c]",
+            register => register,
+        };
+        let sp_offset = text
+            .get(..2)
+            .filter(|sp| sp.eq_ignore_ascii_case("sp"))
+            .map(|_| text[2..].trim_start())
+            .filter(|offset| offset.starts_with(['+', '-']));
+
+        if REGISTERS.contains(&register) {
+            Self::Register(register.to_string())
+        } else if let Some(offset) = sp_offset {
+            Self::SpOffset(offset.to_string())
+        } else if let Some(address) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Self::Memory(address.to_string())
+        } else {
+            Self::Value(text.to_string())
+        }
+    }
+
+    fn expression(&self) -> Option<&str> {
+        match self {
+            Self::Register(_) => None,
+            Self::Value(expression) | Self::Memory(expression) | Self::SpOffset(expression) => {
+                Some(expression)
+            }
+        }
+    }
+}
+
+/// Mnemonic and operands of an opcode, parsed from its disassembly
+struct Template {
+    mnemonic: String,
+    operands: Vec<Operand>,
+    opcode: Vec<Byte>,
+    size: usize,
+    immediate: Immediate,
+}
+
+static TEMPLATES: OnceLock<Vec<Template>> = OnceLock::new();
+
+impl Template {
+    /// Every legal opcode, disassembled with zeroed operands, so assembling is the inverse of decoding
+    fn all() -> &'static [Template] {
+        TEMPLATES.get_or_init(|| {
+            let base = (0..=0xFF)
+                .filter(|opcode| *opcode != CB_PREFIX)
+                .map(|opcode| vec![opcode]);
+            let cb = (0..=0xFF).map(|opcode| vec![CB_PREFIX, opcode]);
+            base.chain(cb)
+                .filter_map(|opcode| {
+                    let sized = SizedInstruction::decode_with(|offset| {
+                        opcode.get(offset as usize).copied().unwrap_or(0)
+                    })?;
+                    if let Instruction::Illegal(_) = sized.instruction {
+                        return None;
+                    }
+                    let (mnemonic, operands) =
+                        split_statement(&format_instruction(sized.instruction, 0));
+                    Some(Template {
+                        mnemonic,
+                        operands: operands
+                            .iter()
+                            .map(|operand| Operand::parse(operand))
+                            .collect(),
+                        opcode,
+                        size: sized.size as usize,
+                        immediate: Immediate::of(sized.instruction),
+                    })
+                })
+                .collect()
+        })
+    }
+
+    fn find(mnemonic: &str, operands: &[Operand]) -> Option<&'static Template> {
+        Self::all()
+            .iter()
+            .find(|template| template.matches(mnemonic, operands))
+    }
+
+    fn matches(&self, mnemonic: &str, operands: &[Operand]) -> bool {
+        let constant = |expression: &str| evaluate(expression, 0, &|_| None).ok();
+        self.mnemonic == mnemonic
+            && self.operands.len() == operands.len()
+            && self
+                .operands
+                .iter()
+                .zip(operands)
+                .all(|(template, operand)| match (template, operand) {
+                    (Operand::Register(a), Operand::Register(b)) => a == b,
+                    // rst vectors and bit numbers are part of the opcode
+                    (Operand::Value(a), Operand::Value(b)) if self.immediate == Immediate::None => {
+                        constant(b).is_some() && constant(a) == constant(b)
+                    }
+                    (Operand::Value(_), Operand::Value(_))
+                    | (Operand::Memory(_), Operand::Memory(_))
+                    | (Operand::SpOffset(_), Operand::SpOffset(_)) => true,
+                    _ => false,
+                })
+    }
+}
+
+enum Statement {
+    Instruction(&'static Template, Vec<Operand>),
+    /// `db` or `dw` values, of the given width in bytes
+    Data(usize, Vec<String>),
+}
+
+/// Statement with where it is placed, for resolving labels and relative jumps
+struct Placed {
+    line: usize,
+    address: i64,
+    /// Last global label, which local labels belong to
+    scope: String,
+    statement: Statement,
+}
+
+/// Assemble SM83 source starting at address 0, e.g. `assemble("ld b, 3\nhalt")`
+pub fn assemble(source: &str) -> Result<Vec<Byte>, AsmError> {
+    assemble_at(source, 0)
+}
+
+/// Assemble SM83 source placed at origin, as RGBDS style instructions written like the
+/// disassembler does, `db` and `dw` data, labels ending with a colon and `;` comments.
+/// Labels starting with a dot are local to the previous label, `@` is the current address
+pub fn assemble_at(source: &str, origin: Address) -> Result<Vec<Byte>, AsmError> {
+    // the size of a statement never depends on labels, so the first pass places them
+    let mut placed = Vec::new();
+    let mut labels = HashMap::new();
+    let mut scope = String::new();
+    let mut address = origin as i64;
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        if let Some((label, rest)) = text.split_once(':') {
+            if is_label(label) {
+                if !label.starts_with('.') {
+                    scope = label.to_string();
+                }
+                let label = qualify(label, &scope);
+                if labels.insert(label.clone(), address).is_some() {
+                    return Err(AsmError::DuplicateLabel(line, label));
+                }
+                // exported labels end with two colons
+                text = rest.trim_start_matches(':').trim();
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, operands) = split_statement(text);
+        let (statement, size) = match mnemonic.as_str() {
+            "db" => (Statement::Data(1, operands.clone()), operands.len()),
+            "dw" => (Statement::Data(2, operands.clone()), operands.len() * 2),
+            _ => {
+                let operands = operands
+                    .iter()
+                    .map(|operand| Operand::parse(operand))
+                    .collect::<Vec<_>>();
+                let template = Template::find(&mnemonic, &operands)
+                    .ok_or_else(|| AsmError::InvalidInstruction(line, text.to_string()))?;
+                (Statement::Instruction(template, operands), template.size)
+            }
+        };
+        placed.push(Placed {
+            line,
+            address,
+            scope: scope.clone(),
+            statement,
+        });
+        address += size as i64;
+    }
+
+    let mut bytes = Vec::new();
+    for Placed {
+        line,
+        address,
+        scope,
+        statement,
+    } in placed
+    {
+        let resolve = |name: &str| match name {
+            "@" => Some(address),
+            name => labels.get(&qualify(name, &scope)).copied(),
+        };
+        let fit = |value: i64, min: i64, max: i64| {
+            (min..=max)
+                .contains(&value)
+                .then_some(value)
+                .ok_or(AsmError::OutOfRange(line, value))
+        };
+        match statement {
+            Statement::Data(width, values) => {
+                for expression in values {
+                    let value = evaluate(&expression, line, &resolve)?;
+                    match width {
+                        1 => bytes.push(fit(value, -0x80, 0xFF)? as Byte),
+                        _ => bytes.extend((fit(value, -0x8000, 0xFFFF)? as Word).to_le_bytes()),
+                    }
+                }
+            }
+            Statement::Instruction(template, operands) => {
+                let start = bytes.len();
+                bytes.extend(&template.opcode);
+                let expression = operands.iter().find_map(Operand::expression);
+                if let (Some(expression), true) =
+                    (expression, template.immediate != Immediate::None)
+                {
+                    let value = evaluate(expression, line, &resolve)?;
+                    match template.immediate {
+                        Immediate::Byte => bytes.push(fit(value, -0x80, 0xFF)? as Byte),
+                        Immediate::Signed => bytes.push(fit(value, -0x80, 0x7F)? as Byte),
+                        Immediate::High => {
+                            let value = if value >= 0xFF00 {
+                                value - 0xFF00
+                            } else {
+                                value
+                            };
+                            bytes.push(fit(value, 0, 0xFF)? as Byte);
+                        }
+                        Immediate::Relative => {
+                            let offset = value - (address + template.size as i64);
+                            bytes.push(fit(offset, -0x80, 0x7F)? as Byte);
+                        }
+                        Immediate::Word => {
+                            bytes.extend((fit(value, -0x8000, 0xFFFF)? as Word).to_le_bytes())
+                        }
+                        Immediate::None => unreachable!(),
+                    }
+                }
+                // stop is followed by a padding byte
+                bytes.resize(start + template.size, 0);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Lowercase mnemonic and the comma separated operands
+fn split_statement(text: &str) -> (String, Vec<String>) {
+    let text = text.trim();
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let operands = match operands.trim() {
+        "" => Vec::new(),
+        operands => operands.split(',').map(|o| o.trim().to_string()).collect(),
+    };
+    (mnemonic.to_ascii_lowercase(), operands)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '#')
+}
+
+fn is_label(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit())
+        && text.chars().all(is_name_char)
+}
+
+/// Full name of a label, local labels are prefixed with their scope
+fn qualify(name: &str, scope: &str) -> String {
+    if name.starts_with('.') {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(char),
+}
+
+/// Split an expression into numbers (`$FF`, `0xFF`, `%1010` or decimal), names and symbols
+fn tokenize(expression: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    let take_while = |chars: &mut Peekable<Chars>, f: fn(char) -> bool| {
+        let mut text = String::new();
+        while let Some(c) = chars.next_if(|c| f(*c)) {
+            text.push(c);
+        }
+        text
+    };
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '+' | '-' | '*' | '(' | ')' | '@' => {
+                chars.next();
+                tokens.push(match c {
+                    '@' => Token::Name("@".to_string()),
+                    c => Token::Symbol(c),
+                });
+            }
+            '$' | '%' => {
+                chars.next();
+                let digits = take_while(&mut chars, |c| c.is_ascii_alphanumeric());
+                let radix = if c == '$' { 16 } else { 2 };
+                tokens.push(Token::Number(i64::from_str_radix(&digits, radix).ok()?));
+            }
+            '0'..='9' => {
+                let digits = take_while(&mut chars, |c| c.is_ascii_alphanumeric());
+                let number = match digits.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => digits.parse(),
+                };
+                tokens.push(Token::Number(number.ok()?));
+            }
+            c if is_name_char(c) => tokens.push(Token::Name(take_while(&mut chars, is_name_char))),
+            _ => return None,
+        }
+    }
+    Some(tokens)
+}
+
+/// Evaluate an expression of numbers and labels with `+`, `-`, `*` and parentheses
+fn evaluate(
+    expression: &str,
+    line: usize,
+    resolve: &dyn Fn(&str) -> Option<i64>,
+) -> Result<i64, AsmError> {
+    let invalid = || AsmError::InvalidExpression(line, expression.trim().to_string());
+    let tokens = tokenize(expression).ok_or_else(invalid)?;
+    let mut parser = ExpressionParser {
+        tokens: &tokens,
+        position: 0,
+        line,
+        resolve,
+    };
+    match parser.sum() {
+        Ok(value) if parser.position == tokens.len() => Ok(value),
+        Ok(_) | Err(None) => Err(invalid()),
+        Err(Some(error)) => Err(error),
+    }
+}
+
+/// Recursive descent parser, a `None` error is a syntax error
+struct ExpressionParser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    line: usize,
+    resolve: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl ExpressionParser<'_> {
+    fn symbol(&mut self, symbols: &[char]) -> Option<char> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(c)) if symbols.contains(c) => {
+                self.position += 1;
+                Some(*c)
+            }
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<i64, Option<AsmError>> {
+        let mut value = self.product()?;
+        while let Some(symbol) = self.symbol(&['+', '-']) {
+            let rhs = self.product()?;
+            value = match symbol {
+                '+' => value.wrapping_add(rhs),
+                _ => value.wrapping_sub(rhs),
+            };
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<i64, Option<AsmError>> {
+        let mut value = self.factor()?;
+        while self.symbol(&['*']).is_some() {
+            value = value.wrapping_mul(self.factor()?);
+        }
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> Result<i64, Option<AsmError>> {
+        if let Some(sign) = self.symbol(&['+', '-']) {
+            let value = self.factor()?;
+            return Ok(if sign == '-' {
+                value.wrapping_neg()
+            } else {
+                value
+            });
+        }
+        if self.symbol(&['(']).is_some() {
+            let value = self.sum()?;
+            return self.symbol(&[')']).map(|_| value).ok_or(None);
+        }
+        let token = self.tokens.get(self.position).ok_or(None)?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(*number),
+            Token::Name(name) => (self.resolve)(name)
+                .ok_or_else(|| Some(AsmError::UnknownLabel(self.line, name.clone()))),
+            Token::Symbol(_) => Err(None),
+        }
+    }
+}
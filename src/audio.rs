@@ -6,6 +6,7 @@ use sdl2::{
 
 use crate::{
     clock::{Clock, CLOCK_FREQ},
+    io::IORegister,
     memory::Memory,
     utils::{bytes2word, get_flag, reset_flag, Address, Byte},
 };
@@ -33,6 +34,48 @@ const C1_PERIOD_HIGH_CONTROL: Address = 0xFF14;
 const C1_LENGTH_ENABLE_FLAG: Byte = 0b0100_0000;
 const TRIGGER_FLAG: Byte = 0b1000_0000;
 
+/// Sound registers, write only bits read as 1
+pub const IO_REGISTERS: &[IORegister] = &[
+    IORegister::new(0xFF10, 0x7F, 0xFF),
+    IORegister::new(C1_LENGTH_DUTY_CYCLE, 0xC0, 0xFF),
+    IORegister::read_write(C1_VOLUME_ENVOLOPE),
+    IORegister::new(C1_PERIOD_LOW, 0x00, 0xFF),
+    IORegister::new(C1_PERIOD_HIGH_CONTROL, 0x40, 0xFF),
+    IORegister::new(0xFF16, 0xC0, 0xFF),
+    IORegister::read_write(0xFF17),
+    IORegister::new(0xFF18, 0x00, 0xFF),
+    IORegister::new(0xFF19, 0x40, 0xFF),
+    IORegister::new(0xFF1A, 0x80, 0xFF),
+    IORegister::new(0xFF1B, 0x00, 0xFF),
+    IORegister::new(0xFF1C, 0x60, 0xFF),
+    IORegister::new(0xFF1D, 0x00, 0xFF),
+    IORegister::new(0xFF1E, 0x40, 0xFF),
+    IORegister::new(0xFF20, 0x00, 0xFF),
+    IORegister::read_write(0xFF21),
+    IORegister::read_write(0xFF22),
+    IORegister::new(0xFF23, 0x40, 0xFF),
+    IORegister::read_write(0xFF24),
+    IORegister::read_write(0xFF25),
+    // channel status bits are read only
+    IORegister::new(MASTER_CONTROL_ADDRESS, 0x8F, 0x80),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x1),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x2),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x3),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x4),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x5),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x6),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x7),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x8),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0x9),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xA),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xB),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xC),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xD),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xE),
+    IORegister::read_write(WAVE_REGISTER_ADDRESS + 0xF),
+];
+
 const AUDIO_FREQ: u32 = 44100;
 const CYCLE_PER_SAMPLE: u32 = CLOCK_FREQ / AUDIO_FREQ;
 const MAX_LENGTH: u32 = 64;
@@ -83,30 +126,30 @@ impl PulseA {
     }
 
     fn initialize_volume(&mut self, memory: &mut Memory) {
-        let flag = memory.read_byte(C1_VOLUME_ENVOLOPE);
+        let flag = memory.read_io(C1_VOLUME_ENVOLOPE);
         let volume = flag >> 4;
         self.volume = volume as f32 / 100.;
     }
 
     fn update_period(&mut self, memory: &mut Memory) {
-        let msb = memory.read_byte(C1_PERIOD_HIGH_CONTROL) & 0b111;
-        let lsb = memory.read_byte(C1_PERIOD_LOW);
+        let msb = memory.read_io(C1_PERIOD_HIGH_CONTROL) & 0b111;
+        let lsb = memory.read_io(C1_PERIOD_LOW);
 
         let freq = bytes2word(lsb, msb);
         self.period = (2048 - freq as u128) * 4;
     }
 
     fn update_duty_cycle(&mut self, memory: &mut Memory) {
-        let value = memory.read_byte(C1_LENGTH_DUTY_CYCLE) >> 6;
+        let value = memory.read_io(C1_LENGTH_DUTY_CYCLE) >> 6;
         self.duty_wave = value as usize;
     }
 
     fn check_trigger(&self, memory: &mut Memory) -> bool {
-        let mut flag_byte = memory.read_byte(C1_PERIOD_HIGH_CONTROL);
+        let mut flag_byte = memory.read_io(C1_PERIOD_HIGH_CONTROL);
 
         if get_flag(flag_byte, TRIGGER_FLAG) {
             reset_flag(&mut flag_byte, TRIGGER_FLAG);
-            memory.write_byte(C1_PERIOD_HIGH_CONTROL, flag_byte);
+            memory.write_io(C1_PERIOD_HIGH_CONTROL, flag_byte);
             true
         } else {
             false
@@ -116,7 +159,7 @@ impl PulseA {
     }
 
     fn update_length_enable(&mut self, memory: &mut Memory) {
-        let flag_byte = memory.read_byte(C1_PERIOD_HIGH_CONTROL);
+        let flag_byte = memory.read_io(C1_PERIOD_HIGH_CONTROL);
         let length_enable = get_flag(flag_byte, C1_LENGTH_ENABLE_FLAG);
 
         match (self.length_enable, length_enable) {
@@ -217,6 +260,6 @@ impl Audio {
     }
 
     fn audio_enabled(&self, memory: &mut Memory) -> bool {
-        memory.read_byte(MASTER_CONTROL_ADDRESS) & AUDIO_ENABLE_FLAG > 0
+        memory.read_io(MASTER_CONTROL_ADDRESS) & AUDIO_ENABLE_FLAG > 0
     }
 }
@@ -0,0 +1,91 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::{App, Arg};
+use gb_rs::disasm::{parse_symbols, RomDisassembly, Symbols};
+use log::{debug, info};
+
+fn main() -> Result<(), String> {
+    env_logger::init();
+
+    let matches = App::new("gb-disasm")
+        .version("1.0")
+        .about("Disassembles a GameBoy ROM file into reassemblable RGBDS assembly")
+        .arg(
+            Arg::with_name("rom_file")
+                .short('f')
+                .long("file")
+                .value_name("FILE")
+                .help("Sets the ROM file to read")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("sym_file")
+                .short('s')
+                .long("sym")
+                .value_name("SYM")
+                .help("Sets the symbol file used for label names, defaults to the ROM file with .sym extension")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short('o')
+                .long("output")
+                .value_name("OUTPUT")
+                .help("Sets the assembly file to write, defaults to the ROM file with .asm extension")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let rom_file = matches.value_of("rom_file").unwrap();
+    info!("Disassembling rom file {}", rom_file);
+    let rom = match fs::read(rom_file) {
+        Ok(fs) => fs,
+        Err(e) => {
+            debug!("Unable to read file {} due to {}", rom_file, e.to_string());
+            return Err(String::from("Unable to read file"));
+        }
+    };
+
+    let sym_file = match matches.value_of("sym_file") {
+        Some(sym_file) => Some(PathBuf::from(sym_file)),
+        None => Some(Path::new(rom_file).with_extension("sym")).filter(|path| path.is_file()),
+    };
+    let symbols = match sym_file {
+        Some(sym_file) => {
+            info!("Using symbol file {}", sym_file.display());
+            match fs::read_to_string(&sym_file) {
+                Ok(fs) => parse_symbols(&fs),
+                Err(e) => {
+                    debug!(
+                        "Unable to read file {} due to {}",
+                        sym_file.display(),
+                        e.to_string()
+                    );
+                    return Err(String::from("Unable to read file"));
+                }
+            }
+        }
+        None => Symbols::new(),
+    };
+
+    let output = match matches.value_of("output") {
+        Some(output) => PathBuf::from(output),
+        None => Path::new(rom_file).with_extension("asm"),
+    };
+    let disassembly = RomDisassembly::new(&rom, &symbols);
+    if let Err(e) = fs::write(&output, disassembly.to_string()) {
+        debug!(
+            "Unable to write file {} due to {}",
+            output.display(),
+            e.to_string()
+        );
+        return Err(String::from("Unable to write file"));
+    }
+    info!("Wrote {}", output.display());
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::utils::Byte;
+
+pub const BOOT_ROM_SIZE: usize = 0x100;
+
+/// Open DMG boot rom, used when no boot rom file is given.
+///
+/// Like the original it scrolls the logo from the cartridge header into view, plays the
+/// chime, checks the header checksum and hands over at 0x100 with the DMG register state.
+/// It does not compare the logo against a stored copy, so any cartridge will boot.
+/// It is only made for DMG, other models need their own boot rom file or skip the boot rom.
+///
+/// Written for gb-rs and dedicated to the public domain under CC0 1.0
+/// (creativecommons.org/publicdomain/zero/1.0). It holds no copy of the Nintendo logo.
+#[rustfmt::skip]
+pub const BOOT_ROM: [Byte; BOOT_ROM_SIZE] = [
+    0x31, 0xFE, 0xFF,    // ld sp, 0xFFFE
+    // clear VRAM
+    0xAF,                // xor a
+    0x21, 0xFF, 0x9F,    // ld hl, 0x9FFF
+    // clear_vram:
+    0x32,                // ld (hl-), a
+    0xCB, 0x7C,          // bit 7, h
+    0x20, 0xFB,          // jr nz, clear_vram
+    // sound on, channel 1 for the chime
+    0x21, 0x26, 0xFF,    // ld hl, 0xFF26
+    0x0E, 0x11,          // ld c, 0x11
+    0x3E, 0x80,          // ld a, 0x80
+    0x32,                // ld (hl-), a
+    0xE2,                // ldh (c), a
+    0x0C,                // inc c
+    0x3E, 0xF3,          // ld a, 0xF3
+    0xE2,                // ldh (c), a
+    0x32,                // ld (hl-), a
+    0x3E, 0x77,          // ld a, 0x77
+    0x77,                // ld (hl), a
+    // background palette
+    0x3E, 0xFC,          // ld a, 0xFC
+    0xE0, 0x47,          // ldh (0x47), a
+    // decode the header logo at 0x0104 into double size tiles 1-24
+    0x11, 0x04, 0x01,    // ld de, 0x0104
+    0x21, 0x10, 0x80,    // ld hl, 0x8010
+    // logo_loop:
+    0x1A,                // ld a, (de)
+    0xCD, 0x8E, 0x00,    // call expand_nibble
+    0x1A,                // ld a, (de)
+    0xCB, 0x37,          // swap a
+    0xCD, 0x8E, 0x00,    // call expand_nibble
+    0x13,                // inc de
+    0x7B,                // ld a, e
+    0xFE, 0x34,          // cp 0x34
+    0x20, 0xF0,          // jr nz, logo_loop
+    // registered mark as tile 25
+    0x11, 0xBC, 0x00,    // ld de, r_tile
+    0x06, 0x08,          // ld b, 0x08
+    // r_loop:
+    0x1A,                // ld a, (de)
+    0x13,                // inc de
+    0x22,                // ld (hl+), a
+    0x23,                // inc hl
+    0x05,                // dec b
+    0x20, 0xF9,          // jr nz, r_loop
+    // tile map, tiles 1-12 above 13-24 in the middle of the screen
+    0x3E, 0x19,          // ld a, 0x19
+    0xEA, 0x10, 0x99,    // ld (0x9910), a
+    0x21, 0x2F, 0x99,    // ld hl, 0x992F
+    // map_row:
+    0x0E, 0x0C,          // ld c, 0x0C
+    // map_loop:
+    0x3D,                // dec a
+    0x28, 0x08,          // jr z, map_done
+    0x32,                // ld (hl-), a
+    0x0D,                // dec c
+    0x20, 0xF9,          // jr nz, map_loop
+    0x2E, 0x0F,          // ld l, 0x0F
+    0x18, 0xF3,          // jr map_row
+    // map_done:
+    // turn on the lcd and scroll the logo down into view
+    0x3E, 0x64,          // ld a, 0x64
+    0xE0, 0x42,          // ldh (0x42), a
+    0x3E, 0x91,          // ld a, 0x91
+    0xE0, 0x40,          // ldh (0x40), a
+    // scroll:
+    0xCD, 0xAF, 0x00,    // call wait_vblank
+    0xF0, 0x42,          // ldh a, (0x42)
+    0x3D,                // dec a
+    0xE0, 0x42,          // ldh (0x42), a
+    0x20, 0xF6,          // jr nz, scroll
+    // two note chime, then hold the logo for a second
+    0x3E, 0x83,          // ld a, 0x83
+    0xCD, 0xA1, 0x00,    // call play_note
+    0x06, 0x05,          // ld b, 0x05
+    0xCD, 0xA8, 0x00,    // call wait_frames
+    0x3E, 0xC1,          // ld a, 0xC1
+    0xCD, 0xA1, 0x00,    // call play_note
+    0x06, 0x3C,          // ld b, 0x3C
+    0xCD, 0xA8, 0x00,    // call wait_frames
+    // verify the header checksum, locking up like the original on a mismatch
+    0x21, 0x34, 0x01,    // ld hl, 0x0134
+    0x06, 0x19,          // ld b, 0x19
+    0x78,                // ld a, b
+    // checksum:
+    0x86,                // add a, (hl)
+    0x2C,                // inc l
+    0x05,                // dec b
+    0x20, 0xFB,          // jr nz, checksum
+    0x86,                // add a, (hl)
+    // lock_up:
+    0x20, 0xFE,          // jr nz, lock_up
+    0x18, 0x36,          // jr hand_off
+    // expand the high nibble of a to a byte, and write it as two rows
+    // expand_nibble:
+    0x4F,                // ld c, a
+    0x06, 0x04,          // ld b, 0x04
+    // expand_bit:
+    0xC5,                // push bc
+    0xCB, 0x11,          // rl c
+    0x17,                // rla
+    0xC1,                // pop bc
+    0xCB, 0x11,          // rl c
+    0x17,                // rla
+    0x05,                // dec b
+    0x20, 0xF5,          // jr nz, expand_bit
+    0x22,                // ld (hl+), a
+    0x23,                // inc hl
+    0x22,                // ld (hl+), a
+    0x23,                // inc hl
+    0xC9,                // ret
+    // play_note:
+    0xE0, 0x13,          // ldh (0x13), a
+    0x3E, 0x87,          // ld a, 0x87
+    0xE0, 0x14,          // ldh (0x14), a
+    0xC9,                // ret
+    // wait_frames:
+    0xCD, 0xAF, 0x00,    // call wait_vblank
+    0x05,                // dec b
+    0x20, 0xFA,          // jr nz, wait_frames
+    0xC9,                // ret
+    // wait_vblank:
+    0xF0, 0x44,          // ldh a, (0x44)
+    0xFE, 0x90,          // cp 0x90
+    0x20, 0xFA,          // jr nz, wait_vblank
+    // wait_leave:
+    0xF0, 0x44,          // ldh a, (0x44)
+    0xFE, 0x90,          // cp 0x90
+    0x28, 0xFA,          // jr z, wait_leave
+    0xC9,                // ret
+    // r_tile:
+    0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA9, 0x42, 0x3C,
+    // leave the DMG register state, with HL at the header checksum
+    // hand_off:
+    0x7E,                // ld a, (hl)
+    0xA7,                // and a
+    0x3E, 0xB0,          // ld a, 0xB0
+    0x20, 0x02,          // jr nz, hand_off_flags
+    0x3E, 0x80,          // ld a, 0x80
+    // hand_off_flags:
+    0x4F,                // ld c, a
+    0x06, 0x01,          // ld b, 0x01
+    0xC5,                // push bc
+    0xF1,                // pop af
+    0x01, 0x13, 0x00,    // ld bc, 0x0013
+    0x11, 0xD8, 0x00,    // ld de, 0x00D8
+    // padding, execution slides into the hand off
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xE0, 0x50,          // ldh (0x50), a
+];
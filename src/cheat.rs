@@ -0,0 +1,212 @@
+use std::{fmt, str::FromStr};
+
+use log::info;
+
+use crate::utils::{Address, Byte};
+
+/// Game Genie codes only patch the cartridge rom
+const GAME_GENIE_END_ADDRESS: Address = 0x7FFF;
+/// GameShark codes only poke ram, starting at VRAM
+const GAME_SHARK_ADDRESS: Address = 0x8000;
+/// GameShark type byte with the top bit set selects an external ram bank
+const GAME_SHARK_BANK_FLAG: Byte = 0x80;
+
+/// Errors from parsing a cheat code
+#[derive(Debug, PartialEq, Eq)]
+pub enum CheatError {
+    /// The code is neither ABC-DEF(-GHI) nor TTVVAAAA
+    InvalidFormat(String),
+    /// The code points outside of the memory it can patch
+    InvalidAddress(Address),
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat(code) => write!(f, "Invalid cheat code {}", code),
+            Self::InvalidAddress(address) => {
+                write!(f, "Cheat code address {:#06X} out of range", address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+/// Decoded cheat code, see [gbdev](https://gbdev.gg8.se/wiki/articles/Gameshark_and_Game_Genie)
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Cheat {
+    /// ABC-DEF-GHI, replaces rom reads at address, only if the rom has the compare byte
+    GameGenie {
+        address: Address,
+        data: Byte,
+        compare: Option<Byte>,
+    },
+    /// TTVVAAAA, writes data to ram every frame
+    GameShark {
+        bank: Option<usize>,
+        address: Address,
+        data: Byte,
+    },
+}
+
+impl FromStr for Cheat {
+    type Err = CheatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        let invalid = || CheatError::InvalidFormat(code.to_string());
+        let digits = code
+            .chars()
+            .filter(|c| *c != '-')
+            .map(|c| c.to_digit(16).map(|d| d as Byte))
+            .collect::<Option<Vec<Byte>>>()
+            .ok_or_else(invalid)?;
+
+        match (code.contains('-'), digits.len()) {
+            (true, 6) | (true, 9) => {
+                let data = digits[0] << 4 | digits[1];
+                let address = ((digits[5] ^ 0xF) as Address) << 12
+                    | (digits[2] as Address) << 8
+                    | (digits[3] as Address) << 4
+                    | digits[4] as Address;
+                // old data is xored with 0xBA and rotated left by 2, the H digit is unused
+                let compare = (digits.len() == 9)
+                    .then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+                if address > GAME_GENIE_END_ADDRESS {
+                    return Err(CheatError::InvalidAddress(address));
+                }
+                Ok(Self::GameGenie {
+                    address,
+                    data,
+                    compare,
+                })
+            }
+            (false, 8) => {
+                let kind = digits[0] << 4 | digits[1];
+                let data = digits[2] << 4 | digits[3];
+                // address is little endian
+                let address = ((digits[6] << 4 | digits[7]) as Address) << 8
+                    | (digits[4] << 4 | digits[5]) as Address;
+                if address < GAME_SHARK_ADDRESS {
+                    return Err(CheatError::InvalidAddress(address));
+                }
+                let bank = (kind & GAME_SHARK_BANK_FLAG != 0)
+                    .then_some((kind & !GAME_SHARK_BANK_FLAG) as usize);
+                Ok(Self::GameShark {
+                    bank,
+                    address,
+                    data,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Cheat code as entered, which can be switched on and off while running
+#[derive(Debug, Clone)]
+pub struct CheatEntry {
+    pub code: String,
+    pub cheat: Cheat,
+    pub enabled: bool,
+}
+
+/// All cheat codes of the running game
+#[derive(Debug, Default, Clone)]
+pub struct Cheats {
+    entries: Vec<CheatEntry>,
+    /// Master switch over all codes
+    disabled: bool,
+}
+
+impl Cheats {
+    /// Parse and add an enabled code, returning its index
+    pub fn add(&mut self, code: &str) -> Result<usize, CheatError> {
+        let cheat = code.parse()?;
+        self.entries.push(CheatEntry {
+            code: code.trim().to_string(),
+            cheat,
+            enabled: true,
+        });
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Add every code of a cheat file, one code per line.
+    /// Text after a code is its description, lines starting with # are comments
+    pub fn load(&mut self, contents: &str) -> Result<usize, CheatError> {
+        let mut count = 0;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let code = line.split_whitespace().next().unwrap();
+            self.add(code)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    pub fn entries(&self) -> &[CheatEntry] {
+        &self.entries
+    }
+
+    /// Enable or disable a single code, returns false if there is no such code
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.entries.get_mut(index) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.disabled
+    }
+
+    /// Enable or disable all codes, keeping the state of each code
+    pub fn toggle(&mut self) {
+        self.disabled = !self.disabled;
+        info!(
+            "Cheats {}",
+            if self.disabled { "disabled" } else { "enabled" }
+        );
+    }
+
+    fn active(&self) -> impl Iterator<Item = &Cheat> {
+        self.entries
+            .iter()
+            .filter(move |entry| !self.disabled && entry.enabled)
+            .map(|entry| &entry.cheat)
+    }
+
+    /// Substitute a byte read from the cartridge rom
+    pub fn patch_rom(&self, address: Address, byte: Byte) -> Byte {
+        self.active()
+            .find_map(|cheat| match *cheat {
+                Cheat::GameGenie {
+                    address: a,
+                    data,
+                    compare,
+                } if a == address && compare.unwrap_or(byte) == byte => Some(data),
+                _ => None,
+            })
+            .unwrap_or(byte)
+    }
+
+    /// Ram writes to apply at the start of each frame, as (bank, address, data)
+    pub fn ram_writes(&self) -> Vec<(Option<usize>, Address, Byte)> {
+        self.active()
+            .filter_map(|cheat| match *cheat {
+                Cheat::GameShark {
+                    bank,
+                    address,
+                    data,
+                } => Some((bank, address, data)),
+                _ => None,
+            })
+            .collect()
+    }
+}
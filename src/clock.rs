@@ -1,17 +1,46 @@
 use crate::{
     cpu::{INTERRUPT_FLAG_ADDRESS, TIMER_FLAG},
+    graphics::Ppu,
+    io::IORegister,
     memory::Memory,
+    model::Model,
     utils::{get_flag, set_flag},
-    utils::{Address, Byte},
+    utils::{Address, Byte, Word},
 };
 
 pub const CLOCK_FREQ: u32 = 4194304;
+/// Header range sent to the SNES by the SGB boot rom
+const SGB_HEADER_ADDRESS: Address = 0x0104;
+const SGB_HEADER_END: Address = 0x0150;
+
+pub const IO_REGISTERS: &[IORegister] = &[
+    // any write resets the divider
+    IORegister::new(Clock::DIV_ADDRESS, 0xFF, 0x00).with_write(Clock::reset_div),
+    IORegister::read_write(Clock::TIMA_ADDRESS),
+    IORegister::read_write(Clock::TMA_ADDRESS),
+    IORegister::new(Clock::TAC_ADDRESS, 0x07, 0x07),
+];
+
+/// TIMA overflow, which reloads TMA one m-cycle late
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy)]
+enum TimaOverflow {
+    #[default]
+    None,
+    /// TIMA reads 0x00 for one m-cycle, a cpu write cancels the reload
+    Overflowed,
+    /// TMA was loaded this m-cycle, cpu writes to TIMA are overridden by TMA
+    Reloaded,
+}
 
 #[derive(Default)]
 pub struct Clock {
-    div_counter: Byte,
-    timer_counter: u32,
+    /// Internal 16 bit counter incremented every t-cycle, DIV is the upper byte
+    div_counter: Word,
+    /// Selected divider bit and'ed with the timer enable, TIMA counts on its falling edge
+    timer_signal: bool,
+    tima_overflow: TimaOverflow,
     timestamp: u128,
+    ppu: Ppu,
 }
 
 impl Clock {
@@ -25,54 +54,133 @@ impl Clock {
     pub fn new() -> Self {
         Clock {
             div_counter: 0,
-            timer_counter: 0,
+            timer_signal: false,
+            tima_overflow: TimaOverflow::None,
             timestamp: 0,
+            ppu: Ppu::new(),
         }
     }
 
-    pub fn tick(&mut self, mcycles: u8, memory: &mut Memory) {
-        // handle divider register
-        let (new_div, overflow) = self.div_counter.overflowing_add(mcycles);
-        self.div_counter = new_div;
-        if overflow {
-            memory.wrapping_add(Self::DIV_ADDRESS, 1);
+    /// Divider phase when the boot rom of the model hands over at 0x100
+    pub fn new_skip_boot(model: Model, memory: &Memory) -> Self {
+        let div_counter = match model {
+            Model::DMG0 => 0x182C,
+            Model::DMG | Model::MGB => 0xABC8,
+            // the SGB boot rom sends the header to the SNES, which takes
+            // one more m-cycle for every 0 bit in 0x0104-0x014F
+            Model::SGB | Model::SGB2 => {
+                let one_bits: u32 = (SGB_HEADER_ADDRESS..SGB_HEADER_END)
+                    .map(|address| memory.read_byte(address).count_ones())
+                    .sum();
+                0xD304 + 4 * ((SGB_HEADER_END - SGB_HEADER_ADDRESS) as u32 * 8 - one_bits) as Word
+            }
+            Model::CGB => 0x2678,
+        };
+        Clock {
+            div_counter,
+            timer_signal: false,
+            tima_overflow: TimaOverflow::None,
+            timestamp: 0,
+            ppu: Ppu::new(),
         }
+    }
 
-        // total counter
-        self.timestamp += mcycles as u128;
+    pub fn tick(&mut self, mcycles: u8, memory: &mut Memory) {
+        // cartridge real time clock
+        memory.tick_rtc(mcycles);
+
+        // oam dma transfer
+        memory.tick_dma(mcycles);
 
-        // handle tima
-        let tac = memory.read_byte(Self::TAC_ADDRESS);
-        if get_flag(tac, Self::TAC_ENABLE_FLAG) {
-            self.timer_counter += 4 * (mcycles as u32);
+        // cpu writes happen between ticks, so they land before the first m-cycle
+        let tima_written = memory.take_io_written(Self::TIMA_ADDRESS);
+        let tma_written = memory.take_io_written(Self::TMA_ADDRESS);
+        if memory.take_io_written(Self::DIV_ADDRESS) {
+            self.div_counter = 0;
+        }
+        // resetting DIV or changing TAC can also make the timer signal fall
+        self.update_timer_signal(memory);
 
-            let frequency = match tac & Self::TAC_CLOCK_SELECT {
-                0 => 4096,
-                1 => 262144,
-                2 => 65536,
-                3 => 16384,
-                _ => unreachable!(),
+        for cycle in 0..mcycles {
+            let written = cycle == 0;
+            self.tima_overflow = match self.tima_overflow {
+                TimaOverflow::Overflowed if written && tima_written => TimaOverflow::None,
+                TimaOverflow::Overflowed => {
+                    self.reload_tima(memory);
+                    TimaOverflow::Reloaded
+                }
+                TimaOverflow::Reloaded => {
+                    if written && (tima_written || tma_written) {
+                        let tma = memory.read_io(Self::TMA_ADDRESS);
+                        memory.write_io(Self::TIMA_ADDRESS, tma);
+                    }
+                    TimaOverflow::None
+                }
+                TimaOverflow::None => TimaOverflow::None,
             };
 
-            while self.timer_counter >= CLOCK_FREQ / frequency {
-                memory.wrapping_add(Self::TIMA_ADDRESS, 1);
+            self.div_counter = self.div_counter.wrapping_add(4);
+            self.update_timer_signal(memory);
 
-                if memory.read_byte(Self::TIMA_ADDRESS) == 0 {
-                    // set timer interrupt and reset timer
-                    let mut interrupt_flags = memory.read_byte(INTERRUPT_FLAG_ADDRESS);
-                    set_flag(&mut interrupt_flags, TIMER_FLAG);
-                    memory.write_byte(INTERRUPT_FLAG_ADDRESS, interrupt_flags);
+            // total counter, the PPU sees every machine cycle of an instruction
+            self.timestamp += 1;
+            self.ppu.render(memory, self.timestamp);
+        }
+        memory.write_io(Self::DIV_ADDRESS, (self.div_counter >> 8) as Byte);
+    }
 
-                    let tma = memory.read_byte(Self::TMA_ADDRESS);
-                    memory.write_byte(Self::TIMA_ADDRESS, tma);
-                }
+    /// Increment TIMA on the falling edge of the timer signal
+    fn update_timer_signal(&mut self, memory: &mut Memory) {
+        let signal = self.timer_bit(memory, self.div_counter);
+        if self.timer_signal && !signal {
+            self.increment_tima(memory);
+        }
+        self.timer_signal = signal;
+    }
 
-                self.timer_counter -= CLOCK_FREQ / frequency;
-            }
+    /// Divider bit selected by TAC, and'ed with the timer enable
+    fn timer_bit(&self, memory: &Memory, counter: Word) -> bool {
+        let tac = memory.read_io(Self::TAC_ADDRESS);
+        let bit = match tac & Self::TAC_CLOCK_SELECT {
+            0 => 9, // 4096 Hz
+            1 => 3, // 262144 Hz
+            2 => 5, // 65536 Hz
+            3 => 7, // 16384 Hz
+            _ => unreachable!(),
+        };
+        get_flag(tac, Self::TAC_ENABLE_FLAG) && counter & (1 << bit) != 0
+    }
+
+    fn increment_tima(&mut self, memory: &mut Memory) {
+        let tima = memory.read_io(Self::TIMA_ADDRESS).wrapping_add(1);
+        memory.write_io(Self::TIMA_ADDRESS, tima);
+
+        if tima == 0 {
+            self.tima_overflow = TimaOverflow::Overflowed;
         }
     }
 
+    /// Set the timer interrupt and load TMA, one m-cycle after the overflow
+    fn reload_tima(&mut self, memory: &mut Memory) {
+        let mut interrupt_flags = memory.read_io(INTERRUPT_FLAG_ADDRESS);
+        set_flag(&mut interrupt_flags, TIMER_FLAG);
+        memory.write_io(INTERRUPT_FLAG_ADDRESS, interrupt_flags);
+
+        let tma = memory.read_io(Self::TMA_ADDRESS);
+        memory.write_io(Self::TIMA_ADDRESS, tma);
+    }
+
+    /// Cpu write to DIV, the internal counter is reset at the start of the next tick
+    fn reset_div(memory: &mut Memory, _byte: Byte) {
+        memory.write_io(Self::DIV_ADDRESS, 0);
+    }
+
     pub fn get_timestamp(&self) -> u128 {
         self.timestamp
     }
+
+    /// Take the frame the PPU finished at the last VBlank, if it was not taken yet
+    pub fn take_frame(&mut self) -> Option<&[Byte]> {
+        self.ppu.take_frame()
+    }
 }
@@ -1,8 +1,15 @@
+use std::{array, sync::OnceLock};
+
 use log::{debug, info};
 
 use crate::{
     clock::Clock,
-    memory::Memory,
+    disasm::format_instruction,
+    header::CgbSupport,
+    io::IORegister,
+    joypad::JOYPAD_REGISTER_ADDRESS,
+    memory::{Bus, Memory},
+    model::Model,
     utils::{bytes2word, get_flag, reset_flag, Address, Byte, ByteOP, SignedByte, Word, WordOP},
 };
 
@@ -20,6 +27,9 @@ pub const LCD_FLAG: Byte = 0b10;
 pub const TIMER_FLAG: Byte = 0b100;
 pub const SERIAL_FLAG: Byte = 0b1000;
 pub const JOYPAD_FLAG: Byte = 0b10000;
+pub const INTERRUPT_MASK: Byte = 0b11111;
+
+pub const IO_REGISTERS: &[IORegister] = &[IORegister::new(INTERRUPT_FLAG_ADDRESS, 0x1F, 0x1F)];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Register {
@@ -80,7 +90,7 @@ impl Register16 {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Condition {
     NonZero,
     Zero,
@@ -100,7 +110,7 @@ impl Condition {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[allow(non_camel_case_types)]
 pub enum Instruction {
     /// Load register (register)
@@ -309,11 +319,15 @@ pub enum Instruction {
     DI,
     /// No operation
     NOP,
+    /// Wait for an interrupt
     HALT,
+    /// Stop the clock until a button is pressed, the byte after it is skipped
     STOP,
+    /// Undefined opcode, which locks up the cpu
+    Illegal(Byte),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct SizedInstruction {
     pub instruction: Instruction,
     pub size: Word,
@@ -330,9 +344,77 @@ impl OpCode {
     }
 }
 
+/// Prefix of the second opcode page
+pub(crate) const CB_PREFIX: Byte = 0xCB;
+
+/// Instruction templates of every opcode with zeroed operands, decoded once from the opcode patterns
+struct DecodeTables {
+    base: [Option<SizedInstruction>; 256],
+    cb: [Instruction; 256],
+}
+
+static DECODE_TABLES: OnceLock<DecodeTables> = OnceLock::new();
+
+impl DecodeTables {
+    fn get() -> &'static Self {
+        DECODE_TABLES.get_or_init(|| Self {
+            base: array::from_fn(|opcode| {
+                SizedInstruction::decode_pattern(|offset| match offset {
+                    0 => opcode as Byte,
+                    _ => 0,
+                })
+            }),
+            cb: array::from_fn(|opcode| {
+                SizedInstruction::decode_pattern(|offset| match offset {
+                    0 => CB_PREFIX,
+                    _ => opcode as Byte,
+                })
+                .unwrap()
+                .instruction
+            }),
+        })
+    }
+}
+
+impl Instruction {
+    /// Fill in the immediate operand of a decode table template
+    fn with_operand(self, operand: Word) -> Self {
+        let n = operand as Byte;
+        let e = n as SignedByte;
+        match self {
+            Self::LD_R_N(r, _) => Self::LD_R_N(r, n),
+            Self::LD_HL_N(_) => Self::LD_HL_N(n),
+            Self::LD_A_NN(_) => Self::LD_A_NN(operand),
+            Self::LD_NN_A(_) => Self::LD_NN_A(operand),
+            Self::LDH_A_N(_) => Self::LDH_A_N(n),
+            Self::LDH_N_A(_) => Self::LDH_N_A(n),
+            Self::LD_RR_NN(rr, _) => Self::LD_RR_NN(rr, operand),
+            Self::LD_NN_SP(_) => Self::LD_NN_SP(operand),
+            Self::LD_HL_SP(_) => Self::LD_HL_SP(e),
+            Self::ADD_N(_) => Self::ADD_N(n),
+            Self::SUB_N(_) => Self::SUB_N(n),
+            Self::AND_N(_) => Self::AND_N(n),
+            Self::OR_N(_) => Self::OR_N(n),
+            Self::ADC_N(_) => Self::ADC_N(n),
+            Self::SBC_N(_) => Self::SBC_N(n),
+            Self::XOR_N(_) => Self::XOR_N(n),
+            Self::CP_N(_) => Self::CP_N(n),
+            Self::ADD_SP_E(_) => Self::ADD_SP_E(e),
+            Self::JP_NN(_) => Self::JP_NN(operand),
+            Self::JP_CC_NN(cc, _) => Self::JP_CC_NN(cc, operand),
+            Self::JR(_) => Self::JR(e),
+            Self::JR_CC(cc, _) => Self::JR_CC(cc, e),
+            Self::CALL(_) => Self::CALL(operand),
+            Self::CALL_CC(cc, _) => Self::CALL_CC(cc, operand),
+            instruction => instruction,
+        }
+    }
+}
+
 impl SizedInstruction {
     // ----- opcodes , left is pattern, right is mask -----
     const NOP: OpCode = OpCode(0, 0b11111111);
+    const STOP: OpCode = OpCode(0x10, 0b11111111);
     /// LOAD for RR, RHL, HLR,
     const LD1: OpCode = OpCode(0b01000000, 0b11000000);
     /// LOAD for RN or HL N
@@ -403,13 +485,54 @@ impl SizedInstruction {
     const CB1: OpCode = OpCode(0b0000_0000, 0b1100_0000);
     /// Interrupt Opcodes
     const IR: OpCode = OpCode(0b1111_0011, 0b1111_0111);
+    /// Opcodes without an instruction
+    const ILLEGAL: [Byte; 11] = [
+        0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+    ];
 
     /// Decode the opcode at address into a SizedInstruction
     pub fn decode(memory: &Memory, address: Address) -> Option<Self> {
-        let opcode = memory.read_byte(address);
-        debug!("Address: {:#04X?}, Opcode: {:#04X?}", address, opcode);
-        let (instruction, size) = if Self::NOP.matches(opcode) {
+        Self::decode_with(|offset| memory.read_byte(address.wrapping_add(offset)))
+    }
+
+    /// Size in bytes of the instruction starting with opcode, including its operands
+    pub fn size_of(opcode: Byte) -> Option<Word> {
+        DecodeTables::get().base[opcode as usize].map(|template| template.size)
+    }
+
+    /// Decode from the bytes fetch returns, with offsets relative to the opcode
+    pub fn decode_with(fetch: impl Fn(Address) -> Byte) -> Option<Self> {
+        let tables = DecodeTables::get();
+        let opcode = fetch(0);
+        if opcode == CB_PREFIX {
+            return Some(SizedInstruction {
+                instruction: tables.cb[fetch(1) as usize],
+                size: 2,
+            });
+        }
+
+        let template = tables.base[opcode as usize]?;
+        let operand = match template.size {
+            2 => fetch(1) as Word,
+            3 => bytes2word(fetch(1), fetch(2)),
+            _ => 0,
+        };
+        Some(SizedInstruction {
+            instruction: template.instruction.with_operand(operand),
+            size: template.size,
+        })
+    }
+
+    /// Decode by matching the opcode patterns, which is only used to build and test the decode tables
+    pub(crate) fn decode_pattern(fetch: impl Fn(Address) -> Byte) -> Option<Self> {
+        let opcode = fetch(0);
+        // checked first, since some of them match the patterns of other opcodes
+        let (instruction, size) = if Self::ILLEGAL.contains(&opcode) {
+            (Instruction::Illegal(opcode), 1)
+        } else if Self::NOP.matches(opcode) {
             (Instruction::NOP, 1)
+        } else if Self::STOP.matches(opcode) {
+            (Instruction::STOP, 2)
         } else if Self::LD1.matches(opcode) {
             let (lr, rr) = Register::get_rr(opcode);
             let instruction = match (lr, rr) {
@@ -421,14 +544,14 @@ impl SizedInstruction {
             (instruction, 1)
         } else if Self::LD2.matches(opcode) {
             let r = Register::get_r(opcode >> 3);
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             let instruction = match r {
                 Register::HL => Instruction::LD_HL_N(n),
                 reg => Instruction::LD_R_N(reg, n),
             };
             (instruction, 2)
         } else if Self::LD3.matches(opcode) {
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             let instruction = if opcode & 1 << 4 != 0 {
                 Instruction::LD_A_NN(nn)
             } else {
@@ -443,7 +566,7 @@ impl SizedInstruction {
             };
             (instruction, 1)
         } else if Self::LD5.matches(opcode) {
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             let instruction = if opcode & 1 << 4 != 0 {
                 Instruction::LDH_A_N(n)
             } else {
@@ -473,18 +596,18 @@ impl SizedInstruction {
             (instruction, 1)
         } else if Self::LD7.matches(opcode) {
             let rr = Register16::get_rr(opcode >> 4, true);
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             let instruction = Instruction::LD_RR_NN(rr, nn);
             (instruction, 3)
         } else if Self::LD8.matches(opcode) {
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             let instruction = Instruction::LD_NN_SP(nn);
             (instruction, 3)
         } else if Self::LD9.matches(opcode) {
             if opcode & 1 == 1 {
                 (Instruction::LD_SP_HL, 1)
             } else {
-                let e = memory.read_byte(address + 1) as SignedByte;
+                let e = fetch(1) as SignedByte;
                 (Instruction::LD_HL_SP(e), 2)
             }
         } else if Self::PUSH_POP.matches(opcode) {
@@ -523,7 +646,7 @@ impl SizedInstruction {
             };
             (instruction, 1)
         } else if Self::ARITH_OP_N.matches(opcode) {
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             let instruction = match opcode.get_high_nibble() {
                 0xc => Instruction::ADD_N(n),
                 0xd => Instruction::SUB_N(n),
@@ -533,7 +656,7 @@ impl SizedInstruction {
             };
             (instruction, 2)
         } else if Self::ARITH_OP_C_N.matches(opcode) {
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             let instruction = match opcode.get_high_nibble() {
                 0xc => Instruction::ADC_N(n),
                 0xd => Instruction::SBC_N(n),
@@ -575,7 +698,7 @@ impl SizedInstruction {
 
             (instruction, 1)
         } else if Self::CALL.matches(opcode) {
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             let instruction = if opcode & 1 != 0 {
                 // ret
                 Instruction::CALL(nn)
@@ -595,20 +718,20 @@ impl SizedInstruction {
             let n = (opcode >> 3) & 0b111;
             (Instruction::RST(n * 8), 1)
         } else if Self::JP.matches(opcode) {
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             (Instruction::JP_NN(nn), 3)
         } else if Self::JP_HL.matches(opcode) {
             (Instruction::JP_HL, 1)
         } else if Self::JP_CC.matches(opcode) {
             let cc = Condition::get_cond(opcode >> 3);
-            let nn = memory.read_word(address + 1);
+            let nn = bytes2word(fetch(1), fetch(2));
             (Instruction::JP_CC_NN(cc, nn), 3)
         } else if Self::JR.matches(opcode) {
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             (Instruction::JR(n as SignedByte), 2)
         } else if Self::JR_CC.matches(opcode) {
             let cc = Condition::get_cond(opcode >> 3);
-            let n = memory.read_byte(address + 1);
+            let n = fetch(1);
             (Instruction::JR_CC(cc, n as SignedByte), 2)
         } else if Self::DAA.matches(opcode) {
             (Instruction::DAA, 1)
@@ -616,7 +739,7 @@ impl SizedInstruction {
             let rr = Register16::get_rr(opcode >> 4, true);
             (Instruction::ADD_HL_RR(rr), 1)
         } else if Self::ADD_SP_E.matches(opcode) {
-            let e = memory.read_byte(address + 1) as SignedByte;
+            let e = fetch(1) as SignedByte;
             (Instruction::ADD_SP_E(e), 2)
         } else if Self::COMP_OP.matches(opcode) {
             let instruction = if opcode & (1 << 4) > 0 {
@@ -638,7 +761,7 @@ impl SizedInstruction {
             };
             (instruction, 1)
         } else if Self::CB.matches(opcode) {
-            let sized_instruction = Self::decode_cb(memory, address + 1);
+            let sized_instruction = Self::decode_cb(|offset| fetch(offset + 1));
             return match sized_instruction {
                 Some(mut instruction) => {
                     instruction.size += 1;
@@ -660,9 +783,8 @@ impl SizedInstruction {
     }
 
     /// Decode CB-Prefixed instructions
-    fn decode_cb(memory: &Memory, address: Address) -> Option<Self> {
-        let opcode = memory.read_byte(address);
-        debug!("CB-Prefixed OpCode: {:#04X?}", opcode);
+    fn decode_cb(fetch: impl Fn(Address) -> Byte) -> Option<Self> {
+        let opcode = fetch(0);
         let r = Register::get_r(opcode);
         let instruction = if Self::CB1.matches(opcode) {
             if opcode & (1 << 3) > 0 {
@@ -781,6 +903,9 @@ pub struct CPU {
     pub pc: Word,                   // program counter
     pub ime: (Option<usize>, bool), // Interrupt Master Enable Flag, left is countdown (if exists), right is the flag
     pub halt: bool,                 // Halt flag
+    pub stop: bool,                 // Stop flag, set until a button is pressed
+    pub locked: bool,               // Lock up flag, set by an illegal opcode until reset
+    halt_bug: bool,                 // Pc fails to increment after the next opcode fetch
 }
 
 impl CPU {
@@ -798,42 +923,77 @@ impl CPU {
             pc: 0x00, // currently start at 0x00,
             ime: (None, false),
             halt: false,
+            stop: false,
+            locked: false,
+            halt_bug: false,
         }
     }
 
-    pub fn new_skip_boot() -> Self {
-        // skip the boot step, and set the register results
+    /// Skip the boot step, and set the registers the boot rom of the model leaves behind
+    pub fn new_skip_boot(model: Model, memory: &Memory) -> Self {
+        let header = memory.get_header();
+        // the DMG and MGB boot roms leave the flags of the header checksum computation
+        let checksum_flags = match header {
+            Some(header) if header.header_checksum == 0 => 0x80,
+            _ => 0xb0,
+        };
+        let cgb_mode = header.is_some_and(|header| header.cgb_support != CgbSupport::None);
+
+        let [a, f, b, c, d, e, h, l] = match model {
+            Model::DMG0 => [0x01, 0x00, 0xff, 0x13, 0x00, 0xc1, 0x84, 0x03],
+            Model::DMG => [0x01, checksum_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::MGB => [0xff, checksum_flags, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            Model::SGB => [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60],
+            Model::SGB2 => [0xff, 0x00, 0x00, 0x14, 0x00, 0x00, 0xc0, 0x60],
+            Model::CGB if cgb_mode => [0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d],
+            Model::CGB => [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7c],
+        };
         Self {
-            a: 0x01,
-            f: 0xb0,
-            b: 0x00,
-            c: 0x13,
-            d: 0x00,
-            e: 0xd8,
-            h: 0x01,
-            l: 0x4d,
+            a,
+            f,
+            b,
+            c,
+            d,
+            e,
+            h,
+            l,
             sp: 0xfffe,
             pc: 0x100, // currently start at 0x100,
             ime: (None, false),
             halt: false,
+            stop: false,
+            locked: false,
+            halt_bug: false,
         }
     }
 
     /// Execute the instruction, and return the clock cycles used
-    pub fn execute(&mut self, memory: &mut Memory, clock: &mut Clock) {
-        let instruction = match SizedInstruction::decode(memory, self.pc) {
-            Some(ins) => ins,
-            None => panic!("Could not decode {:#04X?}", memory.read_byte(self.pc)),
+    pub fn execute<B: Bus>(&mut self, memory: &mut B, clock: &mut Clock) {
+        // fetching the opcode takes a machine cycle, and another for each operand byte
+        let mut bytes = [memory.read_cycle(clock, self.pc), 0, 0];
+        let size = match SizedInstruction::size_of(bytes[0]) {
+            Some(size) => size,
+            None => panic!("Could not decode {:#04X?}", bytes[0]),
         };
+        if self.halt_bug {
+            // the opcode is read again as the first operand
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+        for offset in 1..size {
+            bytes[offset as usize] = memory.read_cycle(clock, self.pc + offset);
+        }
+        let instruction = SizedInstruction::decode_with(|offset| bytes[offset as usize]).unwrap();
 
         debug!(
-            "Decoded Instruction: {:?} {:#04X?}",
-            instruction, instruction
+            "{:#06X}: {}",
+            self.pc,
+            format_instruction(instruction.instruction, self.pc)
         );
+
         match instruction.instruction {
             Instruction::NOP => {
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::ADD_R(r) => {
                 let reg_val = self.get_register(r);
@@ -850,7 +1010,6 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::ADD_N(n) => {
                 let (result, overflow) = self.a.overflowing_add(n);
@@ -863,10 +1022,9 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::ADD_HL => {
-                let value = memory.read_byte(self.get_hl());
+                let value = memory.read_cycle(clock, self.get_hl());
                 let (result, overflow) = self.a.overflowing_add(value);
                 self.zero_flag(result);
                 self.half_carry_flag_add(self.a, value);
@@ -877,7 +1035,6 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SUB_R(r) => {
                 let reg_val = self.get_register(r);
@@ -892,7 +1049,6 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::SUB_N(n) => {
                 let (result, overflow) = self.a.overflowing_sub(n);
@@ -906,10 +1062,9 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SUB_HL => {
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let (result, overflow) = self.a.overflowing_sub(val);
 
                 self.zero_flag(result);
@@ -921,7 +1076,6 @@ impl CPU {
                 }
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::AND_R(r) => {
                 let result = self.a & self.get_register(r);
@@ -931,7 +1085,6 @@ impl CPU {
                 self.reset_flag(SUBTRACT_FLAG);
                 self.reset_flag(CARRY_FLAG);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::AND_N(n) => {
                 let result = self.a & n;
@@ -941,17 +1094,15 @@ impl CPU {
                 self.reset_flag(SUBTRACT_FLAG);
                 self.reset_flag(CARRY_FLAG);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::AND_HL => {
-                let result = self.a & memory.read_byte(self.get_hl());
+                let result = self.a & memory.read_cycle(clock, self.get_hl());
                 self.a = result;
                 self.zero_flag(result);
                 self.set_flag(HALF_CARRY_FLAG);
                 self.reset_flag(SUBTRACT_FLAG);
                 self.reset_flag(CARRY_FLAG);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::OR_R(r) => {
                 let result = self.a | self.get_register(r);
@@ -959,16 +1110,14 @@ impl CPU {
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::OR_HL => {
-                let value = memory.read_byte(self.get_hl());
+                let value = memory.read_cycle(clock, self.get_hl());
                 let result = self.a | value;
                 self.reset_all_flags();
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::OR_N(n) => {
                 let result = self.a | n;
@@ -976,7 +1125,6 @@ impl CPU {
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::XOR_R(r) => {
                 let result = self.a ^ self.get_register(r);
@@ -985,16 +1133,14 @@ impl CPU {
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::XOR_HL => {
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let result = self.a ^ val;
                 self.reset_all_flags();
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::XOR_N(n) => {
                 let result = self.a ^ n;
@@ -1003,7 +1149,6 @@ impl CPU {
                 self.zero_flag(result);
                 self.a = result;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::CP_R(r) => {
                 let reg_val = self.get_register(r);
@@ -1017,11 +1162,10 @@ impl CPU {
                     self.set_flag(CARRY_FLAG);
                 }
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::CP_HL => {
                 let address = self.get_hl();
-                let val = memory.read_byte(address);
+                let val = memory.read_cycle(clock, address);
                 let (result, overflow) = self.a.overflowing_sub(val);
 
                 self.zero_flag(result);
@@ -1032,7 +1176,6 @@ impl CPU {
                     self.set_flag(CARRY_FLAG);
                 }
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::CP_N(n) => {
                 let (result, overflow) = self.a.overflowing_sub(n);
@@ -1045,7 +1188,6 @@ impl CPU {
                     self.set_flag(CARRY_FLAG);
                 }
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::ADC_R(r) => {
                 let reg_val = self.get_register(r);
@@ -1062,7 +1204,6 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::ADC_N(n) => {
                 let cf = self.get_flag(CARRY_FLAG) as Byte;
@@ -1078,10 +1219,9 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::ADC_HL => {
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let cf = self.get_flag(CARRY_FLAG) as Byte;
                 let (res1, ovf1) = self.a.overflowing_add(val);
                 let (res2, ovf2) = res1.overflowing_add(cf);
@@ -1095,7 +1235,6 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SBC_R(r) => {
                 let reg_val = self.get_register(r);
@@ -1112,7 +1251,6 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::SBC_N(n) => {
                 let cf = self.get_flag(CARRY_FLAG) as Byte;
@@ -1128,10 +1266,9 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SBC_HL => {
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let cf = self.get_flag(CARRY_FLAG) as Byte;
                 let (res1, ovf1) = self.a.overflowing_sub(val);
                 let (res2, ovf2) = res1.overflowing_sub(cf);
@@ -1145,61 +1282,51 @@ impl CPU {
                 }
                 self.a = res2;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_R_R(r1, r2) => {
                 let data = self.get_register(r2);
                 self.set_register(r1, data);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::LD_R_N(r, n) => {
                 self.set_register(r, n);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_R_HL(r) => {
-                let data = memory.read_byte(self.get_hl());
+                let data = memory.read_cycle(clock, self.get_hl());
                 self.set_register(r, data);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_RR_NN(rr, nn) => {
                 self.set_register16(rr, nn);
                 self.pc += instruction.size;
-                clock.tick(3, memory);
             }
             Instruction::LD_A_HL_I => {
-                self.a = memory.read_byte(self.get_hl());
+                self.a = memory.read_cycle(clock, self.get_hl());
                 self.set_hl(self.get_hl() + 1);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_A_HL_D => {
-                self.a = memory.read_byte(self.get_hl());
+                self.a = memory.read_cycle(clock, self.get_hl());
                 self.set_hl(self.get_hl() - 1);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LDH_A_C => {
                 let address = bytes2word(self.c, 0xFF);
-                let data = memory.read_byte(address);
+                let data = memory.read_cycle(clock, address);
                 self.a = data;
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LDH_C_A => {
                 let address = bytes2word(self.c, 0xFF);
-                memory.write_byte(address, self.a);
+                memory.write_cycle(clock, address, self.a);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_HL_R(r) => {
                 let address = self.get_hl();
                 let data = self.get_register(r);
-                memory.write_byte(address, data);
+                memory.write_cycle(clock, address, data);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_HL_SP(e) => {
                 let e_i16: i16 = e.into();
@@ -1214,88 +1341,71 @@ impl CPU {
                 }
                 self.set_hl(result);
                 self.pc += instruction.size;
-                clock.tick(3, memory);
+                memory.tick(clock, 1);
             }
             Instruction::LD_HL_A_D => {
-                memory.write_byte(self.get_hl(), self.a);
+                memory.write_cycle(clock, self.get_hl(), self.a);
                 self.set_hl(self.get_hl() - 1);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_HL_A_I => {
-                memory.write_byte(self.get_hl(), self.a);
+                memory.write_cycle(clock, self.get_hl(), self.a);
                 self.set_hl(self.get_hl() + 1);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_A_BC => {
                 self.pc += instruction.size;
                 let address = self.get_register16(Register16::BC);
-                self.a = memory.read_byte(address);
-                clock.tick(2, memory);
+                self.a = memory.read_cycle(clock, address);
             }
             Instruction::LD_A_DE => {
                 self.pc += instruction.size;
                 let address = self.get_register16(Register16::DE);
-                self.a = memory.read_byte(address);
-                clock.tick(2, memory);
+                self.a = memory.read_cycle(clock, address);
             }
             Instruction::LD_BC_A => {
                 let address = self.get_register16(Register16::BC);
-                memory.write_byte(address, self.a);
+                memory.write_cycle(clock, address, self.a);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_DE_A => {
                 let address = self.get_register16(Register16::DE);
-                memory.write_byte(address, self.a);
+                memory.write_cycle(clock, address, self.a);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_A_NN(nn) => {
                 self.pc += instruction.size;
-                clock.tick(2, memory);
-                self.a = memory.read_byte(nn);
-                clock.tick(2, memory);
+                self.a = memory.read_cycle(clock, nn);
             }
             Instruction::LD_NN_A(nn) => {
-                clock.tick(2, memory);
-                memory.write_byte(nn, self.a);
+                memory.write_cycle(clock, nn, self.a);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LDH_N_A(n) => {
                 self.pc += 2;
                 let address = bytes2word(n, 0xFF);
-                clock.tick(1, memory);
-                memory.write_byte(address, self.a);
-                clock.tick(2, memory);
+                memory.write_cycle(clock, address, self.a);
             }
             Instruction::LDH_A_N(n) => {
                 self.pc += 2;
                 let address = bytes2word(n, 0xFF);
-                clock.tick(1, memory);
-                let data = memory.read_byte(address);
+                let data = memory.read_cycle(clock, address);
                 self.a = data;
-                clock.tick(2, memory);
             }
             Instruction::LD_HL_N(n) => {
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), n);
+                memory.write_cycle(clock, self.get_hl(), n);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::LD_NN_SP(nn) => {
                 self.pc += 3;
-                memory.write_byte(nn, self.sp.get_low());
+                memory.write_cycle(clock, nn, self.sp.get_low());
                 let nn = nn + 1;
-                memory.write_byte(nn, self.sp.get_high());
-                clock.tick(5, memory);
+                memory.write_cycle(clock, nn, self.sp.get_high());
             }
             Instruction::LD_SP_HL => {
                 self.sp = self.get_hl();
                 self.pc += instruction.size;
-                clock.tick(2, memory);
+                memory.tick(clock, 1);
             }
             Instruction::INC_R(r) => {
                 let reg_val = self.get_register(r);
@@ -1307,19 +1417,15 @@ impl CPU {
 
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::INC_HL => {
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let (result, _overflow) = val.overflowing_add(1);
 
                 self.zero_flag(result);
                 self.half_carry_flag_add(val, 1);
                 self.reset_flag(SUBTRACT_FLAG);
-
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
-                clock.tick(2, memory);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
             }
             Instruction::DEC_R(r) => {
@@ -1332,19 +1438,16 @@ impl CPU {
 
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::DEC_HL => {
                 let address = self.get_hl();
-                let val = memory.read_byte(address);
+                let val = memory.read_cycle(clock, address);
                 let (result, _overflow) = val.overflowing_sub(1);
 
                 self.zero_flag(result);
                 self.half_carry_flag_sub(val, 1);
                 self.set_flag(SUBTRACT_FLAG);
-                clock.tick(1, memory);
-                memory.write_byte(address, result);
-                clock.tick(2, memory);
+                memory.write_cycle(clock, address, result);
                 self.pc += instruction.size;
             }
             Instruction::INC_RR(rr) => {
@@ -1352,14 +1455,14 @@ impl CPU {
                 let (result, _overflow) = reg_val.overflowing_add(1);
                 self.set_register16(rr, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
+                memory.tick(clock, 1);
             }
             Instruction::DEC_RR(rr) => {
                 let reg_val = self.get_register16(rr);
                 let (result, _overflow) = reg_val.overflowing_sub(1);
                 self.set_register16(rr, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
+                memory.tick(clock, 1);
             }
             Instruction::ADD_HL_RR(rr) => {
                 let reg_val = self.get_register16(rr);
@@ -1373,37 +1476,29 @@ impl CPU {
                 }
                 self.set_hl(result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
+                memory.tick(clock, 1);
             }
             Instruction::SET(b, r) => {
                 let result = self.get_register(r) | (1 << b);
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SET_HL(b) => {
-                clock.tick(1, memory);
-                let result = memory.read_byte(self.get_hl()) | (1 << b);
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                let result = memory.read_cycle(clock, self.get_hl()) | (1 << b);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RES(b, r) => {
                 let mask = !(1 << b);
                 let result = self.get_register(r) & mask;
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RES_HL(b) => {
-                clock.tick(1, memory);
                 let mask = !(1 << b);
-                let result = memory.read_byte(self.get_hl()) & mask;
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                let result = memory.read_cycle(clock, self.get_hl()) & mask;
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::BIT(b, r) => {
                 let result = (self.get_register(r) & (1 << b)) >> b;
@@ -1411,30 +1506,25 @@ impl CPU {
                 self.set_flag(HALF_CARRY_FLAG);
                 self.zero_flag(result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::BIT_HL(b) => {
-                clock.tick(1, memory);
-                let result = (memory.read_byte(self.get_hl()) & (1 << b)) >> b;
+                let result = (memory.read_cycle(clock, self.get_hl()) & (1 << b)) >> b;
                 self.reset_flag(SUBTRACT_FLAG);
                 self.set_flag(HALF_CARRY_FLAG);
                 self.zero_flag(result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::CPL => {
                 self.a = !self.a;
                 self.set_flag(SUBTRACT_FLAG);
                 self.set_flag(HALF_CARRY_FLAG);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::SCF => {
                 self.set_flag(CARRY_FLAG);
                 self.reset_flag(SUBTRACT_FLAG);
                 self.reset_flag(HALF_CARRY_FLAG);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::CCF => {
                 self.reset_flag(SUBTRACT_FLAG);
@@ -1445,7 +1535,6 @@ impl CPU {
                     self.set_flag(CARRY_FLAG);
                 }
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::DAA => {
                 // turn a into decimal form, follows the official implementation
@@ -1472,37 +1561,31 @@ impl CPU {
                 self.reset_flag(HALF_CARRY_FLAG);
                 self.zero_flag(self.a);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::JP_NN(nn) => {
                 self.pc = nn;
-                clock.tick(4, memory);
+                memory.tick(clock, 1);
             }
             Instruction::JP_CC_NN(cc, nn) => {
                 self.pc += 3;
                 if self.get_condition(cc) {
                     self.pc = nn;
-                    clock.tick(4, memory);
-                } else {
-                    clock.tick(3, memory);
+                    memory.tick(clock, 1);
                 }
             }
             Instruction::JP_HL => {
                 self.pc = self.get_hl();
-                clock.tick(1, memory);
             }
             Instruction::JR(e) => {
                 self.pc += 2;
                 self.pc = self.pc.wrapping_add_signed(e.into());
-                clock.tick(3, memory);
+                memory.tick(clock, 1);
             }
             Instruction::JR_CC(cc, e) => {
                 self.pc += 2;
                 if self.get_condition(cc) {
                     self.pc = self.pc.wrapping_add_signed(e.into());
-                    clock.tick(3, memory);
-                } else {
-                    clock.tick(2, memory);
+                    memory.tick(clock, 1);
                 }
             }
             Instruction::ADD_SP_E(e) => {
@@ -1518,61 +1601,58 @@ impl CPU {
                 }
                 self.sp = result;
                 self.pc += instruction.size;
-                clock.tick(4, memory);
+                memory.tick(clock, 2);
             }
             Instruction::PUSH(rr) => {
                 self.pc += 1;
+                memory.tick(clock, 1);
                 self.sp -= 1;
                 let data = self.get_register16(rr);
-                memory.write_byte(self.sp, data.get_high());
+                memory.write_cycle(clock, self.sp, data.get_high());
                 self.sp -= 1;
-                memory.write_byte(self.sp, data.get_low());
-                clock.tick(4, memory);
+                memory.write_cycle(clock, self.sp, data.get_low());
             }
             Instruction::POP(rr) => {
                 self.pc += 1;
-                let lsb = memory.read_byte(self.sp);
+                let lsb = memory.read_cycle(clock, self.sp);
                 self.sp += 1;
-                let msb = memory.read_byte(self.sp);
+                let msb = memory.read_cycle(clock, self.sp);
                 self.sp += 1;
                 self.set_register16(rr, bytes2word(lsb, msb));
-                clock.tick(3, memory);
             }
             Instruction::CALL(nn) => {
                 self.pc += 3;
-                self.push_pc_stack(memory);
+                memory.tick(clock, 1);
+                self.push_pc_stack(memory, clock);
                 self.pc = nn;
-                clock.tick(6, memory);
             }
             Instruction::CALL_CC(cc, nn) => {
                 self.pc += 3;
                 if self.get_condition(cc) {
-                    self.push_pc_stack(memory);
+                    memory.tick(clock, 1);
+                    self.push_pc_stack(memory, clock);
                     self.pc = nn;
-                    clock.tick(6, memory);
-                } else {
-                    clock.tick(3, memory);
                 }
             }
             Instruction::RET => {
                 self.pc += 1;
-                self.pop_pc_stack(memory);
-                clock.tick(4, memory);
+                self.pop_pc_stack(memory, clock);
+                memory.tick(clock, 1);
             }
             Instruction::RET_CC(cc) => {
                 self.pc += 1;
+                // checking the condition takes a cycle
+                memory.tick(clock, 1);
                 if self.get_condition(cc) {
-                    self.pop_pc_stack(memory);
-                    clock.tick(5, memory);
-                } else {
-                    clock.tick(2, memory);
+                    self.pop_pc_stack(memory, clock);
+                    memory.tick(clock, 1);
                 }
             }
             Instruction::RETI => {
                 self.pc += 1;
-                self.pop_pc_stack(memory);
+                self.pop_pc_stack(memory, clock);
                 self.ime_enable_no_delay();
-                clock.tick(4, memory);
+                memory.tick(clock, 1);
             }
             Instruction::RL(r) => {
                 let reg_val = self.get_register(r);
@@ -1585,11 +1665,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RL_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let old_carry = self.get_flag(CARRY_FLAG) as Byte;
                 let result = (val << 1) | old_carry;
                 self.reset_all_flags();
@@ -1597,10 +1675,8 @@ impl CPU {
                 if val & (1 << 7) != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RLC(r) => {
                 let reg_val = self.get_register(r);
@@ -1613,11 +1689,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RLC_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let r7 = val >> 7;
                 let result = (val << 1) | r7;
                 self.reset_all_flags();
@@ -1625,10 +1699,8 @@ impl CPU {
                 if r7 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RLA => {
                 let r = Register::A;
@@ -1641,7 +1713,6 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::RLCA => {
                 let r = Register::A;
@@ -1654,7 +1725,6 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::RR(r) => {
                 let reg_val = self.get_register(r);
@@ -1667,11 +1737,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RR_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let old_carry = self.get_flag(CARRY_FLAG) as Byte;
                 let result = (val >> 1) | (old_carry << 7);
                 self.reset_all_flags();
@@ -1679,10 +1747,8 @@ impl CPU {
                 if val & 1 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RRC(r) => {
                 let reg_val = self.get_register(r);
@@ -1695,11 +1761,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RRC_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let r0 = val & 1;
                 let result = (val >> 1) | (r0 << 7);
                 self.reset_all_flags();
@@ -1707,10 +1771,8 @@ impl CPU {
                 if r0 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RRA => {
                 let r = Register::A;
@@ -1723,7 +1785,6 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::RRCA => {
                 let r = Register::A;
@@ -1736,7 +1797,6 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::SLA(r) => {
                 let reg_val = self.get_register(r);
@@ -1749,11 +1809,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SLA_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let r7 = val >> 7;
                 let result = val << 1;
                 self.reset_all_flags();
@@ -1761,10 +1819,8 @@ impl CPU {
                 if r7 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SRA(r) => {
                 let reg_val = self.get_register(r);
@@ -1778,11 +1834,9 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SRA_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let r7 = val >> 7;
                 let r0 = val & 1;
                 let result = (val >> 1) | (r7 << 7);
@@ -1791,10 +1845,8 @@ impl CPU {
                 if r0 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SRL(r) => {
                 let reg_val = self.get_register(r);
@@ -1806,21 +1858,17 @@ impl CPU {
                 }
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SRL_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let result = val >> 1;
                 self.reset_all_flags();
                 self.zero_flag(result);
                 if val & 1 != 0 {
                     self.set_flag(CARRY_FLAG);
                 }
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SWAP(r) => {
                 let reg_val = self.get_register(r);
@@ -1829,95 +1877,127 @@ impl CPU {
                 self.zero_flag(result);
                 self.set_register(r, result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::SWAP_HL => {
-                clock.tick(1, memory);
-                let val = memory.read_byte(self.get_hl());
+                let val = memory.read_cycle(clock, self.get_hl());
                 let result = (val >> 4) | ((val & 0xf) << 4);
                 self.reset_all_flags();
                 self.zero_flag(result);
-                clock.tick(1, memory);
-                memory.write_byte(self.get_hl(), result);
+                memory.write_cycle(clock, self.get_hl(), result);
                 self.pc += instruction.size;
-                clock.tick(2, memory);
             }
             Instruction::RST(n) => {
                 self.pc += 1;
-                self.push_pc_stack(memory);
+                memory.tick(clock, 1);
+                self.push_pc_stack(memory, clock);
                 self.pc = bytes2word(n, 0x00);
-                clock.tick(4, memory);
             }
             Instruction::EI => {
                 self.ime_enable();
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::DI => {
                 self.ime_disable();
                 self.pc += instruction.size;
-                clock.tick(1, memory);
             }
             Instruction::HALT => {
-                // halt bug
-                // unimplemented!();
-                self.halt = true;
-                self.pc += 1;
-                clock.tick(1, memory);
+                self.pc += instruction.size;
+                if !self.get_ime() && Self::pending_interrupts(memory) != 0 {
+                    // halt bug, the cpu does not halt and pc fails to increment
+                    self.halt_bug = true;
+                } else {
+                    self.halt = true;
+                }
             }
-            _ => {
-                panic!(
-                    "Could not execute {:#04X?} with opcode {:#04X?} at address {:#04X?}",
-                    instruction,
-                    memory.read_byte(self.pc),
-                    self.pc
-                );
+            Instruction::STOP => {
+                self.pc += instruction.size;
+                memory.write_byte(Clock::DIV_ADDRESS, 0);
+                self.stop = true;
+            }
+            Instruction::Illegal(_) => {
+                // pc stays on the opcode, and interrupts are no longer serviced
+                self.locked = true;
             }
         };
 
         self.display_registers(true);
     }
 
-    pub fn handle_interrupts(&mut self, memory: &mut Memory) {
-        let interrupt_enable = memory.read_byte(INTERRUPT_ENABLE_ADDRESS);
-        let interrupt_flag = memory.read_byte(INTERRUPT_FLAG_ADDRESS);
-        let mut flag_bytes = interrupt_enable & interrupt_flag;
+    /// Interrupts both requested and enabled, which wake the cpu from halt
+    fn pending_interrupts<B: Bus>(memory: &B) -> Byte {
+        memory.read_byte(INTERRUPT_ENABLE_ADDRESS)
+            & memory.read_byte(INTERRUPT_FLAG_ADDRESS)
+            & INTERRUPT_MASK
+    }
 
-        // handle halt
-        if flag_bytes != 0 || self.get_ime() {
-            self.halt = false;
+    /// Leave stop mode once a button of a selected joypad line is pressed
+    pub fn wake_from_stop(&mut self, memory: &Memory) {
+        // button bits are low while pressed
+        if memory.read_byte(JOYPAD_REGISTER_ADDRESS) & 0x0F != 0x0F {
+            self.stop = false;
         }
+    }
+
+    /// Wake from halt, and dispatch the highest priority pending interrupt if ime is set.
+    /// Dispatch takes 5 machine cycles: 2 wait cycles, the pc push and the jump
+    pub fn handle_interrupts<B: Bus>(&mut self, memory: &mut B, clock: &mut Clock) {
+        if self.locked {
+            return;
+        }
+
+        // handle halt, which ends on a pending interrupt even if ime is not set
+        if Self::pending_interrupts(memory) == 0 {
+            return;
+        }
+        self.halt = false;
 
         if !self.get_ime() {
             return;
         }
 
-        if flag_bytes != 0 {
-            self.ime_disable();
-            self.push_pc_stack(memory);
-            if get_flag(flag_bytes, VBLANK_FLAG) {
-                debug!("VBLANK Interrupt");
-                reset_flag(&mut flag_bytes, VBLANK_FLAG);
-                self.pc = 0x40;
-            } else if get_flag(flag_bytes, LCD_FLAG) {
-                debug!("LCD Interrupt");
-                reset_flag(&mut flag_bytes, LCD_FLAG);
-                self.pc = 0x48;
-            } else if get_flag(flag_bytes, TIMER_FLAG) {
-                debug!("TIMER Interrupt");
-                reset_flag(&mut flag_bytes, TIMER_FLAG);
-                self.pc = 0x50;
-            } else if get_flag(flag_bytes, SERIAL_FLAG) {
-                debug!("SERIAL Interrupt");
-                reset_flag(&mut flag_bytes, SERIAL_FLAG);
-                self.pc = 0x58;
-            } else if get_flag(flag_bytes, JOYPAD_FLAG) {
-                info!("JOYPAD Interrupt");
-                reset_flag(&mut flag_bytes, JOYPAD_FLAG);
-                self.pc = 0x60;
-            }
+        self.ime_disable();
+        if self.halt_bug {
+            // the interrupt returns to the halt instead of after it
+            self.halt_bug = false;
+            self.pc -= 1;
         }
-        memory.write_byte(INTERRUPT_FLAG_ADDRESS, flag_bytes);
+        memory.tick(clock, 2);
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_cycle(clock, self.sp, self.pc.get_high());
+
+        // the interrupt is picked after the high byte push, which can overwrite IE
+        let flag_bytes = Self::pending_interrupts(memory);
+        let (flag, vector) = if get_flag(flag_bytes, VBLANK_FLAG) {
+            debug!("VBLANK Interrupt");
+            (Some(VBLANK_FLAG), 0x40)
+        } else if get_flag(flag_bytes, LCD_FLAG) {
+            debug!("LCD Interrupt");
+            (Some(LCD_FLAG), 0x48)
+        } else if get_flag(flag_bytes, TIMER_FLAG) {
+            debug!("TIMER Interrupt");
+            (Some(TIMER_FLAG), 0x50)
+        } else if get_flag(flag_bytes, SERIAL_FLAG) {
+            debug!("SERIAL Interrupt");
+            (Some(SERIAL_FLAG), 0x58)
+        } else if get_flag(flag_bytes, JOYPAD_FLAG) {
+            info!("JOYPAD Interrupt");
+            (Some(JOYPAD_FLAG), 0x60)
+        } else {
+            // IE was overwritten, pc jumps to 0x0000 without acknowledging anything
+            debug!("Interrupt cancelled");
+            (None, 0x00)
+        };
+        // only the serviced interrupt is acknowledged
+        if let Some(flag) = flag {
+            let mut interrupt_flag = memory.read_byte(INTERRUPT_FLAG_ADDRESS);
+            reset_flag(&mut interrupt_flag, flag);
+            memory.write_byte(INTERRUPT_FLAG_ADDRESS, interrupt_flag);
+        }
+
+        self.sp = self.sp.wrapping_sub(1);
+        memory.write_cycle(clock, self.sp, self.pc.get_low());
+        self.pc = vector;
+        memory.tick(clock, 1);
     }
 
     pub fn get_hl(&self) -> Word {
@@ -2060,18 +2140,18 @@ impl CPU {
     }
 
     /// Push pc register values to [sp-1],[sp-2]
-    fn push_pc_stack(&mut self, memory: &mut Memory) {
+    fn push_pc_stack<B: Bus>(&mut self, memory: &mut B, clock: &mut Clock) {
         self.sp -= 1;
-        memory.write_byte(self.sp, self.pc.get_high());
+        memory.write_cycle(clock, self.sp, self.pc.get_high());
         self.sp -= 1;
-        memory.write_byte(self.sp, self.pc.get_low());
+        memory.write_cycle(clock, self.sp, self.pc.get_low());
     }
 
     /// Pop pc register values from [sp+1],[sp+2]
-    fn pop_pc_stack(&mut self, memory: &mut Memory) {
-        let lsb = memory.read_byte(self.sp);
+    fn pop_pc_stack<B: Bus>(&mut self, memory: &mut B, clock: &mut Clock) {
+        let lsb = memory.read_cycle(clock, self.sp);
         self.sp += 1;
-        let msb = memory.read_byte(self.sp);
+        let msb = memory.read_cycle(clock, self.sp);
         self.sp += 1;
         self.pc = bytes2word(lsb, msb);
     }
@@ -2098,7 +2178,7 @@ impl CPU {
         self.ime = (None, false);
     }
 
-    /// Step the ime delay
+    /// Step the ime delay, before handling interrupts so EI takes effect after the next instruction
     pub fn ime_step(&mut self) {
         if let Some(mut delay) = self.ime.0 {
             delay -= 1;
@@ -2146,6 +2226,27 @@ impl CPU {
         }
     }
 
+    /// Registers and the 4 bytes at pc, as one line of a gameboy-doctor log
+    pub fn doctor_state(&self, memory: &Memory) -> String {
+        format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            self.a,
+            self.f,
+            self.b,
+            self.c,
+            self.d,
+            self.e,
+            self.h,
+            self.l,
+            self.sp,
+            self.pc,
+            memory.read_byte(self.pc),
+            memory.read_byte(self.pc.wrapping_add(1)),
+            memory.read_byte(self.pc.wrapping_add(2)),
+            memory.read_byte(self.pc.wrapping_add(3)),
+        )
+    }
+
     fn display_flags(&self) -> String {
         format!(
             "{}{}{}{}",
@@ -0,0 +1,594 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    ops::{Range, RangeInclusive},
+};
+
+use crate::{
+    cpu::{Condition, Instruction, Register, Register16, SizedInstruction},
+    memory::Memory,
+    utils::{Address, Byte, SignedByte},
+};
+
+/// Size of a switchable rom bank
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// One disassembled instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: Address,
+    pub bytes: Vec<Byte>,
+    pub instruction: Instruction,
+}
+
+impl Line {
+    /// Assembly text of the instruction, e.g. `jr nz, $0150`
+    pub fn text(&self) -> String {
+        format_instruction(self.instruction, self.address)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:<8}  {}", self.address, bytes, self.text())
+    }
+}
+
+/// Format an instruction as RGBDS assembly, relative jumps are resolved from its address
+pub fn format_instruction(instruction: Instruction, address: Address) -> String {
+    format_instruction_with(instruction, address, |_| None)
+}
+
+/// Format an instruction as RGBDS assembly, using the label names it returns for jump and call targets
+pub fn format_instruction_with(
+    instruction: Instruction,
+    address: Address,
+    label: impl Fn(Address) -> Option<String>,
+) -> String {
+    use Instruction::*;
+
+    let location = |nn: Address| label(nn).unwrap_or_else(|| format!("${:04X}", nn));
+    let target = |e: SignedByte| location(address.wrapping_add(2).wrapping_add(e as Address));
+    match instruction {
+        LD_R_R(l, r) => format!("ld {}, {}", reg(l), reg(r)),
+        LD_R_N(r, n) => format!("ld {}, ${:02X}", reg(r), n),
+        LD_R_HL(r) => format!("ld {}, [hl]", reg(r)),
+        LD_HL_R(r) => format!("ld [hl], {}", reg(r)),
+        LD_HL_N(n) => format!("ld [hl], ${:02X}", n),
+        LD_A_BC => "ld a, [bc]".to_string(),
+        LD_A_DE => "ld a, [de]".to_string(),
+        LD_BC_A => "ld [bc], a".to_string(),
+        LD_DE_A => "ld [de], a".to_string(),
+        LD_A_NN(nn) => format!("ld a, [${:04X}]", nn),
+        LD_NN_A(nn) => format!("ld [${:04X}], a", nn),
+        LDH_A_C => "ldh a, [c]".to_string(),
+        LDH_C_A => "ldh [c], a".to_string(),
+        LDH_A_N(n) => format!("ldh a, [$FF{:02X}]", n),
+        LDH_N_A(n) => format!("ldh [$FF{:02X}], a", n),
+        LD_A_HL_D => "ld a, [hl-]".to_string(),
+        LD_A_HL_I => "ld a, [hl+]".to_string(),
+        LD_HL_A_D => "ld [hl-], a".to_string(),
+        LD_HL_A_I => "ld [hl+], a".to_string(),
+        LD_RR_NN(rr, nn) => format!("ld {}, ${:04X}", reg16(rr), nn),
+        LD_NN_SP(nn) => format!("ld [${:04X}], sp", nn),
+        LD_SP_HL => "ld sp, hl".to_string(),
+        LD_HL_SP(e) if e < 0 => format!("ld hl, sp - {}", e.unsigned_abs()),
+        LD_HL_SP(e) => format!("ld hl, sp + {}", e),
+        PUSH(rr) => format!("push {}", reg16(rr)),
+        POP(rr) => format!("pop {}", reg16(rr)),
+        ADD_R(r) => format!("add a, {}", reg(r)),
+        ADD_HL => "add a, [hl]".to_string(),
+        ADD_N(n) => format!("add a, ${:02X}", n),
+        SUB_R(r) => format!("sub {}", reg(r)),
+        SUB_HL => "sub [hl]".to_string(),
+        SUB_N(n) => format!("sub ${:02X}", n),
+        AND_R(r) => format!("and {}", reg(r)),
+        AND_HL => "and [hl]".to_string(),
+        AND_N(n) => format!("and ${:02X}", n),
+        OR_R(r) => format!("or {}", reg(r)),
+        OR_HL => "or [hl]".to_string(),
+        OR_N(n) => format!("or ${:02X}", n),
+        ADC_R(r) => format!("adc a, {}", reg(r)),
+        ADC_HL => "adc a, [hl]".to_string(),
+        ADC_N(n) => format!("adc a, ${:02X}", n),
+        SBC_R(r) => format!("sbc a, {}", reg(r)),
+        SBC_HL => "sbc a, [hl]".to_string(),
+        SBC_N(n) => format!("sbc a, ${:02X}", n),
+        XOR_R(r) => format!("xor {}", reg(r)),
+        XOR_HL => "xor [hl]".to_string(),
+        XOR_N(n) => format!("xor ${:02X}", n),
+        CP_R(r) => format!("cp {}", reg(r)),
+        CP_HL => "cp [hl]".to_string(),
+        CP_N(n) => format!("cp ${:02X}", n),
+        INC_R(r) => format!("inc {}", reg(r)),
+        INC_RR(rr) => format!("inc {}", reg16(rr)),
+        INC_HL => "inc [hl]".to_string(),
+        DEC_R(r) => format!("dec {}", reg(r)),
+        DEC_RR(rr) => format!("dec {}", reg16(rr)),
+        DEC_HL => "dec [hl]".to_string(),
+        ADD_HL_RR(rr) => format!("add hl, {}", reg16(rr)),
+        ADD_SP_E(e) => format!("add sp, {}", e),
+        RLCA => "rlca".to_string(),
+        RRCA => "rrca".to_string(),
+        RLA => "rla".to_string(),
+        RRA => "rra".to_string(),
+        RLC(r) => format!("rlc {}", reg(r)),
+        RLC_HL => "rlc [hl]".to_string(),
+        RRC(r) => format!("rrc {}", reg(r)),
+        RRC_HL => "rrc [hl]".to_string(),
+        RL(r) => format!("rl {}", reg(r)),
+        RL_HL => "rl [hl]".to_string(),
+        RR(r) => format!("rr {}", reg(r)),
+        RR_HL => "rr [hl]".to_string(),
+        SLA(r) => format!("sla {}", reg(r)),
+        SLA_HL => "sla [hl]".to_string(),
+        SRA(r) => format!("sra {}", reg(r)),
+        SRA_HL => "sra [hl]".to_string(),
+        SWAP(r) => format!("swap {}", reg(r)),
+        SWAP_HL => "swap [hl]".to_string(),
+        SRL(r) => format!("srl {}", reg(r)),
+        SRL_HL => "srl [hl]".to_string(),
+        BIT(b, r) => format!("bit {}, {}", b, reg(r)),
+        BIT_HL(b) => format!("bit {}, [hl]", b),
+        RES(b, r) => format!("res {}, {}", b, reg(r)),
+        RES_HL(b) => format!("res {}, [hl]", b),
+        SET(b, r) => format!("set {}, {}", b, reg(r)),
+        SET_HL(b) => format!("set {}, [hl]", b),
+        JP_NN(nn) => format!("jp {}", location(nn)),
+        JP_HL => "jp hl".to_string(),
+        JP_CC_NN(cc, nn) => format!("jp {}, {}", cond(cc), location(nn)),
+        JR(e) => format!("jr {}", target(e)),
+        JR_CC(cc, e) => format!("jr {}, {}", cond(cc), target(e)),
+        CALL(nn) => format!("call {}", location(nn)),
+        CALL_CC(cc, nn) => format!("call {}, {}", cond(cc), location(nn)),
+        RET => "ret".to_string(),
+        RET_CC(cc) => format!("ret {}", cond(cc)),
+        RETI => "reti".to_string(),
+        RST(n) => format!("rst ${:02X}", n),
+        CCF => "ccf".to_string(),
+        SCF => "scf".to_string(),
+        DAA => "daa".to_string(),
+        CPL => "cpl".to_string(),
+        EI => "ei".to_string(),
+        DI => "di".to_string(),
+        NOP => "nop".to_string(),
+        HALT => "halt".to_string(),
+        STOP => "stop".to_string(),
+        Illegal(opcode) => format!("db ${:02X}", opcode),
+    }
+}
+
+/// Disassemble the instructions starting in the address range, reading through the memory bus
+pub fn disassemble(memory: &Memory, range: Range<Address>) -> Vec<Line> {
+    disassemble_with(|address| memory.read_byte(address), range)
+}
+
+/// Disassemble the single instruction at the address, reading through the memory bus
+pub fn disassemble_instruction(memory: &Memory, address: Address) -> Line {
+    let fetch = |offset: Address| memory.read_byte(address.wrapping_add(offset));
+    let sized = SizedInstruction::decode_with(fetch).unwrap_or(SizedInstruction {
+        instruction: Instruction::Illegal(fetch(0)),
+        size: 1,
+    });
+    Line {
+        address,
+        bytes: (0..sized.size).map(fetch).collect(),
+        instruction: sized.instruction,
+    }
+}
+
+/// Disassemble a whole bank of a raw rom, at the address it is mapped to
+pub fn disassemble_bank(rom: &[Byte], bank: usize) -> Vec<Line> {
+    let start = bank * ROM_BANK_SIZE;
+    let data = rom.get(start..).unwrap_or_default();
+    let data = &data[..data.len().min(ROM_BANK_SIZE)];
+    disassemble_bytes(data, bank_base(bank))
+}
+
+/// Disassemble raw bytes, where the first byte is at the base address
+pub fn disassemble_bytes(data: &[Byte], base: Address) -> Vec<Line> {
+    let end = base.saturating_add(data.len() as Address);
+    disassemble_with(
+        |address| {
+            data.get(address.wrapping_sub(base) as usize)
+                .copied()
+                .unwrap_or(0)
+        },
+        base..end,
+    )
+}
+
+/// Linear sweep over the range, an instruction cut off by the end of the range becomes data
+fn disassemble_with(fetch: impl Fn(Address) -> Byte, range: Range<Address>) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut address = range.start;
+    while address < range.end {
+        let sized = SizedInstruction::decode_with(|offset| fetch(address.wrapping_add(offset)))
+            .filter(|sized| (address as usize + sized.size as usize) <= range.end as usize)
+            .unwrap_or(SizedInstruction {
+                instruction: Instruction::Illegal(fetch(address)),
+                size: 1,
+            });
+        lines.push(Line {
+            address,
+            bytes: (0..sized.size)
+                .map(|offset| fetch(address.wrapping_add(offset)))
+                .collect(),
+            instruction: sized.instruction,
+        });
+        address = match address.checked_add(sized.size) {
+            Some(address) => address,
+            None => break,
+        };
+    }
+    lines
+}
+
+/// Names of rom locations, keyed by bank and address
+pub type Symbols = HashMap<(usize, Address), String>;
+
+/// Labels of the entry point and the RST and interrupt vectors, where tracing starts
+const ENTRY_POINTS: [(Address, &str); 14] = [
+    (0x0100, "Boot"),
+    (0x0000, "RST_00"),
+    (0x0008, "RST_08"),
+    (0x0010, "RST_10"),
+    (0x0018, "RST_18"),
+    (0x0020, "RST_20"),
+    (0x0028, "RST_28"),
+    (0x0030, "RST_30"),
+    (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "LCDCInterrupt"),
+    (0x0050, "TimerOverflowInterrupt"),
+    (0x0058, "SerialTransferCompleteInterrupt"),
+    (0x0060, "JoypadTransitionInterrupt"),
+];
+/// Writes to this range select the switchable rom bank on every MBC
+const BANK_SELECT: RangeInclusive<Address> = 0x2000..=0x3FFF;
+/// The rom is only mapped below this address
+const ROM_END_ADDRESS: Address = 0x8000;
+
+/// Parse a .sym file as written by RGBDS or WLA, lines of `BB:AAAA name`.
+/// Only the labels section of WLA files is read, names are made valid RGBDS labels
+pub fn parse_symbols(contents: &str) -> Symbols {
+    let mut symbols = Symbols::new();
+    let mut labels = true;
+    for line in contents.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[') {
+            labels = section == "labels]";
+            continue;
+        }
+        if !labels || line.starts_with(';') {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let location = parts.next().and_then(|location| {
+            let (bank, address) = location.split_once(':')?;
+            Some((
+                usize::from_str_radix(bank, 16).ok()?,
+                Address::from_str_radix(address, 16).ok()?,
+            ))
+        });
+        if let (Some(location), Some(name)) = (location, parts.next()) {
+            symbols.insert(location, label_name(name));
+        }
+    }
+    symbols
+}
+
+/// Keep only characters every assembler accepts in a global label
+fn label_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+/// Disassembly of a whole rom, following the control flow from the entry points.
+/// Bytes never reached are kept as data, so the output reassembles to the same rom
+pub struct RomDisassembly<'a> {
+    rom: &'a [Byte],
+    /// Instructions found in each bank, by address
+    code: Vec<BTreeMap<Address, SizedInstruction>>,
+    /// Bytes of each bank which are part of an instruction
+    covered: Vec<Vec<bool>>,
+    /// Bank and address of jump and call targets, by the location of the instruction
+    targets: HashMap<(usize, Address), (usize, Address)>,
+    labels: BTreeMap<(usize, Address), String>,
+}
+
+impl<'a> RomDisassembly<'a> {
+    pub fn new(rom: &'a [Byte], symbols: &Symbols) -> Self {
+        let banks = rom.len().div_ceil(ROM_BANK_SIZE).max(1);
+        let mut disassembly = Self {
+            rom,
+            code: vec![BTreeMap::new(); banks],
+            covered: Vec::new(),
+            targets: HashMap::new(),
+            labels: BTreeMap::new(),
+        };
+        disassembly.covered = (0..banks)
+            .map(|bank| vec![false; disassembly.bank_data(bank).len()])
+            .collect();
+        for (address, name) in ENTRY_POINTS {
+            disassembly.labels.insert((0, address), name.to_string());
+        }
+        for (address, _) in ENTRY_POINTS {
+            disassembly.trace(0, address);
+        }
+        for (&(bank, address), name) in symbols {
+            let location = (
+                if address < ROM_BANK_SIZE as Address {
+                    0
+                } else {
+                    bank
+                },
+                address,
+            );
+            if address < ROM_END_ADDRESS && location.0 < banks {
+                disassembly.labels.insert(location, name.clone());
+            }
+        }
+
+        // the same name can show up in several banks
+        let mut names = HashSet::new();
+        for ((bank, address), name) in disassembly.labels.iter_mut() {
+            if !names.insert(name.clone()) {
+                *name = format!("{}_{:03X}_{:04X}", name, bank, address);
+            }
+        }
+        disassembly
+    }
+
+    /// Number of rom banks, the last one may be partial
+    pub fn bank_count(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Check if an instruction starts at the address of the bank
+    pub fn is_code(&self, bank: usize, address: Address) -> bool {
+        self.code
+            .get(bank)
+            .is_some_and(|code| code.contains_key(&address))
+    }
+
+    /// Label at the address of the bank, if it is not inside an instruction
+    pub fn label(&self, bank: usize, address: Address) -> Option<&str> {
+        let offset = address.checked_sub(bank_base(bank))? as usize;
+        let inside = self.covered.get(bank)?.get(offset).copied().unwrap_or(true);
+        self.labels
+            .get(&(bank, address))
+            .filter(|_| !inside || self.is_code(bank, address))
+            .map(String::as_str)
+    }
+
+    fn bank_data(&self, bank: usize) -> &'a [Byte] {
+        let data = self.rom.get(bank * ROM_BANK_SIZE..).unwrap_or_default();
+        &data[..data.len().min(ROM_BANK_SIZE)]
+    }
+
+    /// Decode a new instruction, unless it leaves the bank or overlaps known code
+    fn decode(&self, bank: usize, address: Address) -> Option<SizedInstruction> {
+        let data = self.bank_data(bank);
+        let offset = address.checked_sub(bank_base(bank))? as usize;
+        if offset >= data.len() {
+            return None;
+        }
+        let sized = SizedInstruction::decode_with(|index| {
+            data.get(offset + index as usize).copied().unwrap_or(0)
+        })?;
+        let end = offset + sized.size as usize;
+        (end <= data.len() && !self.covered[bank][offset..end].contains(&true)).then_some(sized)
+    }
+
+    /// Bank of a jump target, the switchable bank is only known after a bank select
+    fn target_bank(
+        &self,
+        bank: usize,
+        target: Address,
+        switch_bank: Option<usize>,
+    ) -> Option<usize> {
+        match target {
+            0x0000..=0x3FFF => Some(0),
+            0x4000..=0x7FFF if bank != 0 => Some(bank),
+            0x4000..=0x7FFF => switch_bank.or((self.bank_count() == 2).then_some(1)),
+            _ => None,
+        }
+        .filter(|bank| *bank < self.bank_count())
+    }
+
+    /// Follow every path from the address, until a jump, return or already traced code
+    fn trace(&mut self, bank: usize, address: Address) {
+        use Instruction::*;
+
+        let mut pending = vec![(bank, address, None)];
+        while let Some((bank, mut address, mut switch_bank)) = pending.pop() {
+            let mut accumulator = None;
+            while let Some(sized) = self.decode(bank, address) {
+                let offset = (address - bank_base(bank)) as usize;
+                self.code[bank].insert(address, sized);
+                self.covered[bank][offset..offset + sized.size as usize].fill(true);
+
+                match sized.instruction {
+                    LD_NN_A(nn) if bank == 0 && BANK_SELECT.contains(&nn) => {
+                        // bank 0 selects bank 1 on MBC1
+                        switch_bank = accumulator.map(|n: Byte| (n as usize).max(1));
+                    }
+                    _ => (),
+                }
+                accumulator = match sized.instruction {
+                    LD_R_N(Register::A, n) => Some(n),
+                    _ => None,
+                };
+
+                let target = match sized.instruction {
+                    JP_NN(nn) | JP_CC_NN(_, nn) | CALL(nn) | CALL_CC(_, nn) => Some(nn),
+                    JR(e) | JR_CC(_, e) => Some(address.wrapping_add(2).wrapping_add(e as Address)),
+                    RST(n) => Some(n as Address),
+                    _ => None,
+                };
+                if let Some(target) = target {
+                    if let Some(target_bank) = self.target_bank(bank, target, switch_bank) {
+                        let kind = match sized.instruction {
+                            CALL(_) | CALL_CC(..) | RST(_) => "Call",
+                            _ => "Jump",
+                        };
+                        self.targets.insert((bank, address), (target_bank, target));
+                        self.labels.entry((target_bank, target)).or_insert_with(|| {
+                            format!("{}_{:03X}_{:04X}", kind, target_bank, target)
+                        });
+                        pending.push((
+                            target_bank,
+                            target,
+                            switch_bank.filter(|_| target_bank == 0),
+                        ));
+                    }
+                }
+
+                if matches!(
+                    sized.instruction,
+                    JP_NN(_) | JR(_) | JP_HL | RET | RETI | Illegal(_)
+                ) {
+                    break;
+                }
+                address = match address.checked_add(sized.size) {
+                    Some(address) => address,
+                    None => break,
+                };
+            }
+        }
+    }
+
+    /// Assembly text of an instruction, with labels for its target
+    fn instruction_text(&self, bank: usize, address: Address, sized: SizedInstruction) -> String {
+        let data = self.bank_data(bank);
+        let offset = (address - bank_base(bank)) as usize;
+        match sized.instruction {
+            // rgbasm always writes stop as 10 00
+            Instruction::STOP if data[offset + 1] != 0 => {
+                format!("db $10, ${:02X}", data[offset + 1])
+            }
+            // rst operands are fixed vectors, not labels
+            Instruction::RST(_) => format_instruction(sized.instruction, address),
+            instruction => format_instruction_with(instruction, address, |target| {
+                self.targets
+                    .get(&(bank, address))
+                    .filter(|(_, address)| *address == target)
+                    .and_then(|&(bank, address)| self.label(bank, address))
+                    .map(str::to_string)
+            }),
+        }
+    }
+}
+
+/// Writes the rom as RGBDS assembly, one section per bank
+impl fmt::Display for RomDisassembly<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for bank in 0..self.bank_count() {
+            if bank == 0 {
+                writeln!(f, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+            } else {
+                writeln!(
+                    f,
+                    "\nSECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:X}]",
+                    bank, bank
+                )?;
+            }
+
+            let data = self.bank_data(bank);
+            let mut bytes = Vec::new();
+            let mut offset = 0;
+            while offset < data.len() {
+                let address = bank_base(bank) + offset as Address;
+                let label = self.label(bank, address);
+                let code = self.code[bank].get(&address);
+                if label.is_some() || code.is_some() || bytes.len() == DATA_LINE_SIZE {
+                    write_data(f, &mut bytes)?;
+                }
+                if let Some(label) = label {
+                    writeln!(f, "\n{}:", label)?;
+                }
+                match code {
+                    Some(sized) => {
+                        writeln!(f, "    {}", self.instruction_text(bank, address, *sized))?;
+                        offset += sized.size as usize;
+                    }
+                    None => {
+                        bytes.push(data[offset]);
+                        offset += 1;
+                    }
+                }
+            }
+            write_data(f, &mut bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bytes per db line of data
+const DATA_LINE_SIZE: usize = 16;
+
+fn write_data(f: &mut fmt::Formatter<'_>, bytes: &mut Vec<Byte>) -> fmt::Result {
+    if bytes.is_empty() {
+        return Ok(());
+    }
+    let bytes = bytes
+        .drain(..)
+        .map(|byte| format!("${:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(", ");
+    writeln!(f, "    db {}", bytes)
+}
+
+/// Address a bank is mapped to
+fn bank_base(bank: usize) -> Address {
+    if bank == 0 {
+        0
+    } else {
+        ROM_BANK_SIZE as Address
+    }
+}
+
+fn reg(r: Register) -> &'static str {
+    match r {
+        Register::A => "a",
+        Register::B => "b",
+        Register::C => "c",
+        Register::D => "d",
+        Register::E => "e",
+        Register::H => "h",
+        Register::L => "l",
+        Register::HL => "[hl]",
+    }
+}
+
+fn reg16(rr: Register16) -> &'static str {
+    match rr {
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::SP => "sp",
+        Register16::AF => "af",
+    }
+}
+
+fn cond(cc: Condition) -> &'static str {
+    match cc {
+        Condition::NonZero => "nz",
+        Condition::Zero => "z",
+        Condition::NotCarry => "nc",
+        Condition::Carry => "c",
+    }
+}
@@ -1,6 +1,11 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
 
-use log::info;
+use log::{error, info};
 use sdl2::{
     event::{Event, EventType},
     keyboard::Keycode,
@@ -8,12 +13,16 @@ use sdl2::{
 
 use crate::{
     audio::Audio,
-    clock::Clock,
+    cheat::CheatError,
+    clock::{Clock, CLOCK_FREQ},
     cpu::{Instruction, SizedInstruction, CPU},
+    disasm::disassemble_instruction,
     graphics::Graphics,
+    header::CartridgeError,
     joypad::Joypad,
     memory::Memory,
-    utils::Address,
+    model::Model,
+    utils::{Address, Byte},
 };
 
 pub struct GameBoy {
@@ -24,13 +33,41 @@ pub struct GameBoy {
     clock: Clock,
     joypad: Joypad,
     dbg: Debugger,
+    save_path: Option<PathBuf>,
+    model: Model,
+    /// gameboy-doctor log of every executed instruction
+    trace: Option<BufWriter<File>>,
 }
 
+/// Machine cycles between writes of a changed save file (about 1 second)
+const SAVE_INTERVAL: u128 = CLOCK_FREQ as u128 / 4;
+
 /// Struct to hold all debugger constructs
 struct Debugger {
     pause: bool,
     step: bool,
     breakpoints: HashSet<Breakpoint>,
+    handler: Box<dyn FnMut(DebugEvent)>,
+}
+
+/// Events the debugger reports to the frontend
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DebugEvent {
+    /// Execution paused on a breakpoint
+    Breakpoint(Address),
+    /// The cpu locked up on an illegal opcode, emulation keeps running without it
+    LockUp { address: Address, opcode: Byte },
+}
+
+/// Default debugger event handler, which only logs the event
+fn log_event(event: DebugEvent) {
+    match event {
+        DebugEvent::Breakpoint(address) => info!("Breakpoint: {:#04X?}", address),
+        DebugEvent::LockUp { address, opcode } => error!(
+            "CPU locked up on illegal opcode {:#04X?} at {:#04X?}",
+            opcode, address
+        ),
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -45,9 +82,14 @@ impl Debugger {
             pause: false,
             step: false,
             breakpoints: HashSet::new(),
+            handler: Box::new(log_event),
         }
     }
 
+    fn raise(&mut self, event: DebugEvent) {
+        (self.handler)(event);
+    }
+
     fn toggle_pause(&mut self) {
         self.pause = !self.pause;
     }
@@ -81,7 +123,8 @@ impl Debugger {
             false
         } else if self.check_breakpoints(cpu, memory) {
             self.pause = true;
-            info!("Breakpoint: {:#04X?}", cpu.pc);
+            self.raise(DebugEvent::Breakpoint(cpu.pc));
+            info!("{}", disassemble_instruction(memory, cpu.pc));
             cpu.display_registers(false);
             true
         } else {
@@ -91,7 +134,7 @@ impl Debugger {
 }
 
 impl GameBoy {
-    pub fn new(graphics_enabled: bool, audio_enabled: bool) -> Self {
+    pub fn new(model: Model, graphics_enabled: bool, audio_enabled: bool) -> Self {
         // Initialize SDL
         let context = sdl2::init().unwrap();
 
@@ -111,17 +154,104 @@ impl GameBoy {
             joypad: Joypad::new(),
             clock: Clock::new(),
             dbg: Debugger::new(),
+            save_path: None,
+            model,
+            trace: None,
         }
     }
 
-    pub fn load_rom(&mut self, rom_data: Vec<u8>) {
-        self.memory.load_cartidge(rom_data);
+    pub fn load_rom(&mut self, rom_data: Vec<u8>) -> Result<(), CartridgeError> {
+        self.memory.load_cartidge(rom_data)
     }
 
     pub fn load_boot(&mut self, boot_data: Vec<u8>) {
         self.memory.load_boot(boot_data);
     }
 
+    /// Start at 0x100 without a boot rom, with the cpu and I/O state it leaves behind.
+    /// Must be called after load_rom
+    pub fn skip_boot(&mut self) {
+        self.memory.skip_boot(self.model);
+        self.cpu = CPU::new_skip_boot(self.model, &self.memory);
+        self.clock = Clock::new_skip_boot(self.model, &self.memory);
+    }
+
+    /// Load battery backed ram from save_path, which is also where the ram gets saved.
+    /// Does nothing if the cartridge has no battery, must be called after load_rom
+    pub fn load_save(&mut self, save_path: PathBuf) {
+        if !self.memory.has_battery() {
+            return;
+        }
+        match fs::read(&save_path) {
+            Ok(data) => {
+                info!("Loading save file {}", save_path.display());
+                self.memory.load_save_data(&data);
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                info!("No save file {}", save_path.display());
+            }
+            Err(e) => error!("Unable to read save file {}: {}", save_path.display(), e),
+        }
+        self.save_path = Some(save_path);
+    }
+
+    /// Write battery backed ram to the save file
+    fn write_save(&mut self) {
+        if let Some(ref save_path) = self.save_path {
+            if let Err(e) = fs::write(save_path, self.memory.get_save_data()) {
+                error!("Unable to write save file {}: {}", save_path.display(), e);
+            }
+        }
+    }
+
+    /// Enable or disable blocking cpu VRAM and OAM access based on the PPU mode
+    pub fn set_ppu_restrictions(&mut self, enabled: bool) {
+        self.memory.set_ppu_restrictions(enabled);
+    }
+
+    /// Add an enabled Game Genie or GameShark code, returning its index
+    pub fn add_cheat(&mut self, code: &str) -> Result<usize, CheatError> {
+        self.memory.cheats_mut().add(code)
+    }
+
+    /// Add every code of a cheat file, returning how many were added
+    pub fn load_cheats(&mut self, contents: &str) -> Result<usize, CheatError> {
+        self.memory.cheats_mut().load(contents)
+    }
+
+    /// Enable or disable a single cheat code by index
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        self.memory.cheats_mut().set_enabled(index, enabled)
+    }
+
+    /// Write the cpu state before every instruction to a file, in the gameboy-doctor format.
+    /// LY reads 0x90 while tracing, and the trace only matches the reference logs after skip_boot
+    pub fn set_trace_file(&mut self, path: &Path) -> io::Result<()> {
+        self.trace = Some(BufWriter::new(File::create(path)?));
+        self.memory.set_ly_stub(true);
+        Ok(())
+    }
+
+    /// Append the cpu state to the trace file, tracing stops on a write error
+    fn write_trace(&mut self) {
+        if let Some(ref mut trace) = self.trace {
+            if let Err(e) = writeln!(trace, "{}", self.cpu.doctor_state(&self.memory)) {
+                error!("Unable to write trace file: {}", e);
+                self.trace = None;
+            }
+        }
+    }
+
+    /// Replace the handler of debugger events such as breakpoints and lock ups
+    pub fn on_debug_event(&mut self, handler: impl FnMut(DebugEvent) + 'static) {
+        self.dbg.handler = Box::new(handler);
+    }
+
+    /// Check if the cartridge rumble motor is on
+    pub fn get_rumble(&self) -> bool {
+        self.memory.get_rumble()
+    }
+
     pub fn run(mut self) {
         // self.dbg.add_breakpoint(Breakpoint::Addr(0x039e));
         // self.dbg.add_breakpoint(Breakpoint::Inst(Instruction::EI));
@@ -130,6 +260,7 @@ impl GameBoy {
         let mut last_timestamp = 0;
         let mut last_time = std::time::Instant::now();
         let mut last_poll_time = std::time::Instant::now();
+        let mut last_save_timestamp = 0;
 
         // disable all events, enable only ones needed
         if let Some(ref mut graphics) = self.graphics {
@@ -146,7 +277,7 @@ impl GameBoy {
             graphics.event_pump.enable_event(EventType::KeyUp);
         }
 
-        loop {
+        'running: loop {
             // poll every 0.1s
             if let Some(ref mut graphics) = self.graphics {
                 if last_poll_time.elapsed().as_millis() > 50 {
@@ -160,7 +291,7 @@ impl GameBoy {
                             | Event::KeyDown {
                                 keycode: Some(Keycode::Q),
                                 ..
-                            } => return,
+                            } => break 'running,
                             Event::KeyDown {
                                 keycode: Some(Keycode::P),
                                 ..
@@ -169,6 +300,10 @@ impl GameBoy {
                                 keycode: Some(Keycode::RightBracket),
                                 ..
                             } => self.dbg.toggle_step(),
+                            Event::KeyDown {
+                                keycode: Some(Keycode::C),
+                                ..
+                            } => self.memory.cheats_mut().toggle(),
                             Event::KeyDown {
                                 keycode: Some(k), ..
                             } => self.joypad.handle_button(k, true, &mut self.memory),
@@ -188,19 +323,37 @@ impl GameBoy {
             // update joypad
             self.joypad.update(&mut self.memory);
 
-            // start executing gb
-            if self.cpu.halt {
+            // start executing gb, the clock does not run in stop mode
+            if self.cpu.stop {
+                self.cpu.wake_from_stop(&self.memory);
+            } else if self.cpu.halt || self.cpu.locked {
                 self.clock.tick(1, &mut self.memory);
             } else {
+                self.write_trace();
                 self.cpu.execute(&mut self.memory, &mut self.clock);
+                if self.cpu.locked {
+                    self.dbg.raise(DebugEvent::LockUp {
+                        address: self.cpu.pc,
+                        opcode: self.memory.read_byte(self.cpu.pc),
+                    });
+                }
             }
 
-            self.cpu.handle_interrupts(&mut self.memory);
-
             self.cpu.ime_step();
 
+            self.cpu
+                .handle_interrupts(&mut self.memory, &mut self.clock);
+
+            // write changed battery backed ram
+            if self.clock.get_timestamp() - last_save_timestamp > SAVE_INTERVAL {
+                last_save_timestamp = self.clock.get_timestamp();
+                if self.memory.take_ram_dirty() {
+                    self.write_save();
+                }
+            }
+
             // serial output debug
-            if self.memory.read_byte(0xff02) != 0 {
+            if self.memory.read_byte(0xff02) & 0x80 != 0 {
                 let c = self.memory.read_byte(0xff01) as char;
                 print!("{}", c);
                 self.memory.write_byte(0xff02, 0);
@@ -213,11 +366,11 @@ impl GameBoy {
                 }
             }
 
-            // render graphics
+            // show the finished frame
             if let Some(ref mut graphics) = self.graphics {
-                // non gb related keydowns
-
-                graphics.render(&mut self.memory, self.clock.get_timestamp());
+                if let Some(frame) = self.clock.take_frame() {
+                    graphics.present(frame);
+                }
                 if self.clock.get_timestamp() - last_timestamp > 17476 {
                     while last_time.elapsed().as_millis() < 16 {
                         graphics.timer.delay(1);
@@ -227,5 +380,12 @@ impl GameBoy {
                 }
             }
         }
+
+        self.write_save();
+        if let Some(ref mut trace) = self.trace {
+            if let Err(e) = trace.flush() {
+                error!("Unable to write trace file: {}", e);
+            }
+        }
     }
 }
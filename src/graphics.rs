@@ -13,6 +13,7 @@ use std::fmt;
 
 use crate::{
     cpu::{INTERRUPT_FLAG_ADDRESS, LCD_FLAG, VBLANK_FLAG},
+    io::IORegister,
     memory::Memory,
     utils::{get_flag, set_flag, set_flag_ref, Address, Byte, Word},
 };
@@ -27,7 +28,7 @@ const SCY_ADDRESS: Address = 0xFF42;
 const SCX_ADDRESS: Address = 0xFF43;
 const WY_ADDRESS: Address = 0xFF4A;
 const WX_ADDRESS: Address = 0xFF4B;
-const LY_ADDRESS: Address = 0xFF44;
+pub const LY_ADDRESS: Address = 0xFF44;
 const LYC_ADDRESS: Address = 0xFF45;
 
 // LCDC flags
@@ -62,6 +63,21 @@ const LYC_EQ_LY_FLAG: Byte = 0b0000_0100;
 
 const SCANLINE_CYCLES: u128 = 114;
 
+pub const IO_REGISTERS: &[IORegister] = &[
+    IORegister::read_write(LCDC_ADDRESS),
+    // mode and lyc flag are read only
+    IORegister::new(LCD_STATUS_ADDRESS, 0x7F, 0x78),
+    IORegister::read_write(SCY_ADDRESS),
+    IORegister::read_write(SCX_ADDRESS),
+    IORegister::new(LY_ADDRESS, 0xFF, 0x00),
+    IORegister::read_write(LYC_ADDRESS),
+    IORegister::read_write(BG_PALETTE_ADDRESS),
+    IORegister::read_write(OBP0_ADDRESS),
+    IORegister::read_write(OBP1_ADDRESS),
+    IORegister::read_write(WY_ADDRESS),
+    IORegister::read_write(WX_ADDRESS),
+];
+
 const BLACK: Color = Color::RGB(0, 0, 0);
 const DARK_GREY: Color = Color::RGB(48, 48, 48);
 const LIGHT_GREY: Color = Color::RGB(139, 139, 139);
@@ -162,8 +178,8 @@ impl Tile {
             let lsb_address = address + 2 * (x as Address);
             let msb_address = address + 2 * (x as Address) + 1;
 
-            let lsb = memory.read_byte(lsb_address);
-            let msb = memory.read_byte(msb_address);
+            let lsb = memory.read_video(lsb_address);
+            let msb = memory.read_video(msb_address);
 
             for y in 0..8 {
                 let b = 7 - y;
@@ -275,7 +291,7 @@ impl BgFIFO {
                 Entry::Vacant(vacant) => {
                     let tile_idx = tile_pos.i + tile_pos.j * 32;
                     let tile_num_address = map_address + (tile_idx as Address);
-                    let tile_num = memory.read_byte(tile_num_address);
+                    let tile_num = memory.read_video(tile_num_address);
                     let tile_start_address = if get_flag(lcdc, BGW_TILES_DATA_FLAG) {
                         0x8000 + BYTES_PER_TILE * (tile_num as Address)
                     } else {
@@ -317,7 +333,7 @@ impl FIFO for BgFIFO {
         };
         self.in_window = Self::in_window(self.screen_pos, memory);
         self.fifo.clear();
-        self.lcdc = Graphics::get_lcdc(memory);
+        self.lcdc = Ppu::get_lcdc(memory);
 
         self.fetch(memory);
     }
@@ -396,7 +412,7 @@ impl FIFO for ObjFIFO {
         };
         self.fifo.clear();
         self.obj_attr.clear();
-        self.lcdc = Graphics::get_lcdc(memory);
+        self.lcdc = Ppu::get_lcdc(memory);
 
         let mut line_pixels = [Pixel::new(0, PixelSource::Object { number: 0 }); SCREEN_WIDTH];
 
@@ -405,10 +421,10 @@ impl FIFO for ObjFIFO {
             for obj_idx in 0..OBJ_COUNT {
                 let obj_address = OAM_ADDRESS + 4 * (obj_idx as Address);
 
-                let y_pos = memory.read_byte(obj_address) as usize;
-                let x_pos = memory.read_byte(obj_address + 1) as usize;
-                let tile_number = memory.read_byte(obj_address + 2) as Address;
-                let flag = memory.read_byte(obj_address + 3);
+                let y_pos = memory.read_video(obj_address) as usize;
+                let x_pos = memory.read_video(obj_address + 1) as usize;
+                let tile_number = memory.read_video(obj_address + 2) as Address;
+                let flag = memory.read_video(obj_address + 3);
 
                 // TODO: modify for 16x8 objects
                 if y_pos <= self.screen_y + 16
@@ -495,14 +511,18 @@ pub struct Graphics {
     pub event_pump: EventPump,
     pub texture_creator: TextureCreator<WindowContext>,
     pub timer: TimerSubsystem,
+}
 
-    // gb related
+/// Pixel processing unit, stepped by the clock every machine cycle with or without graphics
+pub struct Ppu {
     line_y: usize,
     screen_buffer: [Byte; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
     last_timestamp: u128,
     bg_fifo: BgFIFO,
     obj_fifo: ObjFIFO,
     last_ppu_mode: PPUMode,
+    /// A frame was finished at the start of VBlank and not taken yet
+    frame_ready: bool,
 }
 
 impl Graphics {
@@ -535,15 +555,53 @@ impl Graphics {
             event_pump,
             texture_creator,
             timer,
+        }
+    }
+
+    /// Show a frame finished by the PPU
+    pub fn present(&mut self, frame: &[Byte]) {
+        let mut texture = self
+            .texture_creator
+            .create_texture_target(
+                PixelFormatEnum::RGB24,
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            )
+            .unwrap();
+        texture.update(None, frame, SCREEN_WIDTH * 3).unwrap();
+        self.canvas.copy(&texture, None, None).unwrap();
+        self.canvas.present();
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
             screen_buffer: [0; PIXEL_COUNT * 3],
             line_y: 0,
             last_timestamp: 0,
             bg_fifo: BgFIFO::new(),
             obj_fifo: ObjFIFO::new(),
             last_ppu_mode: PPUMode::Mode1 { line: 153 },
+            frame_ready: false,
         }
     }
 
+    /// Take the frame finished at the last VBlank, if it was not taken yet
+    pub fn take_frame(&mut self) -> Option<&[Byte]> {
+        if !self.frame_ready {
+            return None;
+        }
+        self.frame_ready = false;
+        Some(&self.screen_buffer)
+    }
+
     /// Render according to gb specifications [pandocs](https://gbdev.io/pandocs/Rendering.html)
     /// Each line requires 456 dots = 114 machine cycles,
     /// First 20 mcycles are OAM scan,
@@ -589,22 +647,14 @@ impl Graphics {
                     self.set_lyc(memory);
                 }
                 (PPUMode::Mode0 { line: l1 }, PPUMode::Mode1 { line: l2 }) if l1 + 1 == l2 => {
-                    // render to screen if vblank
+                    // frame is done at vblank
                     self.set_lyc(memory);
                     self.set_vblank_int(memory);
-                    let mut texture = self
-                        .texture_creator
-                        .create_texture_target(
-                            PixelFormatEnum::RGB24,
-                            SCREEN_WIDTH as u32,
-                            SCREEN_HEIGHT as u32,
-                        )
-                        .unwrap();
-                    texture
-                        .update(None, &self.screen_buffer, SCREEN_WIDTH * 3)
-                        .unwrap();
-                    self.canvas.copy(&texture, None, None).unwrap();
-                    self.canvas.present();
+                    self.frame_ready = true;
+                    // GameShark codes poke ram at the start of every vblank the lcd shows
+                    if get_flag(Self::get_lcdc(memory), LCDC_ENABLE_FLAG) {
+                        memory.apply_cheats();
+                    }
                 }
                 (PPUMode::Mode1 { line: l1 }, PPUMode::Mode1 { line: l2 }) if l1 + 1 == l2 => {
                     // newline in vblank mode
@@ -699,7 +749,7 @@ impl Graphics {
 
     /// Set ppu stat flag and LCD interrupt flag
     fn set_ppu(&self, ppu_mode: PPUMode, memory: &mut Memory) {
-        let stat_flag = memory.read_byte(LCD_STATUS_ADDRESS) & !0b11;
+        let stat_flag = memory.read_io(LCD_STATUS_ADDRESS) & !0b11;
         let new_stat_flag = stat_flag | ppu_mode.get_num();
 
         // interrupt
@@ -717,18 +767,18 @@ impl Graphics {
             _ => (),
         }
         memory.write_byte(INTERRUPT_FLAG_ADDRESS, int_flag);
-        memory.write_byte(LCD_STATUS_ADDRESS, new_stat_flag);
+        memory.write_io(LCD_STATUS_ADDRESS, new_stat_flag);
     }
 
     /// Set ly and lyc int/flags
     fn set_lyc(&self, memory: &mut Memory) {
-        memory.write_byte(LY_ADDRESS, self.line_y as Byte);
+        memory.write_io(LY_ADDRESS, self.line_y as Byte);
         let lyc = memory.read_byte(LYC_ADDRESS) as usize;
         if lyc == self.line_y {
             // set the lyc == ly flag in stat
-            let stat_flag = memory.read_byte(LCD_STATUS_ADDRESS);
+            let stat_flag = memory.read_io(LCD_STATUS_ADDRESS);
             let new_stat_flag = set_flag_ref(stat_flag, LYC_EQ_LY_FLAG);
-            memory.write_byte(LCD_STATUS_ADDRESS, new_stat_flag);
+            memory.write_io(LCD_STATUS_ADDRESS, new_stat_flag);
 
             if get_flag(stat_flag, LCY_INT_FLAG) {
                 let mut int_flag = memory.read_byte(INTERRUPT_FLAG_ADDRESS);
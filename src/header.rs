@@ -0,0 +1,247 @@
+use std::fmt;
+
+use crate::utils::{Address, Byte, Word};
+
+const TITLE_ADDRESS: Address = 0x0134;
+const MANUFACTURER_ADDRESS: Address = 0x013F;
+const CGB_FLAG_ADDRESS: Address = 0x0143;
+const NEW_LICENSEE_ADDRESS: Address = 0x0144;
+const SGB_FLAG_ADDRESS: Address = 0x0146;
+const CARTRIDGE_TYPE_ADDRESS: Address = 0x0147;
+const ROM_SIZE_ADDRESS: Address = 0x0148;
+const RAM_SIZE_ADDRESS: Address = 0x0149;
+const DESTINATION_ADDRESS: Address = 0x014A;
+const OLD_LICENSEE_ADDRESS: Address = 0x014B;
+const VERSION_ADDRESS: Address = 0x014C;
+const HEADER_CHECKSUM_ADDRESS: Address = 0x014D;
+const GLOBAL_CHECKSUM_ADDRESS: Address = 0x014E;
+/// First byte after the header
+const HEADER_END: usize = 0x0150;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+/// Errors from loading a cartridge rom
+#[derive(Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The rom is shorter than the header or the size the header declares
+    Truncated {
+        expected: usize,
+        actual: usize,
+    },
+    /// The header checksum does not match, which real hardware refuses to boot
+    HeaderChecksum {
+        expected: Byte,
+        actual: Byte,
+    },
+    UnsupportedType(Byte),
+    UnsupportedRomSize(Byte),
+    UnsupportedRamSize(Byte),
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { expected, actual } => write!(
+                f,
+                "ROM is truncated, expected {:#X} bytes but got {:#X}",
+                expected, actual
+            ),
+            Self::HeaderChecksum { expected, actual } => write!(
+                f,
+                "Header checksum mismatch, header has {:#04X} but computed {:#04X}",
+                expected, actual
+            ),
+            Self::UnsupportedType(code) => write!(f, "Unsupported cartridge type {:#04X}", code),
+            Self::UnsupportedRomSize(code) => write!(f, "Unsupported ROM size {:#04X}", code),
+            Self::UnsupportedRamSize(code) => write!(f, "Unsupported RAM size {:#04X}", code),
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+/// Color support declared by the CGB flag
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CgbSupport {
+    /// Monochrome game
+    None,
+    /// Color enhanced, still runs on monochrome models
+    Compatible,
+    /// Color only
+    Only,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// Cartridge header at 0x0100-0x014F, see [pandocs](https://gbdev.io/pandocs/The_Cartridge_Header.html)
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// 4 character manufacturer code, only present in newer color era roms
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    /// Two character licensee code, used when the old code is 0x33
+    pub new_licensee_code: String,
+    pub old_licensee_code: Byte,
+    pub cartridge_type: Byte,
+    pub rom_size: Byte,
+    pub ram_size: Byte,
+    pub destination: Destination,
+    pub version: Byte,
+    pub header_checksum: Byte,
+    pub global_checksum: Word,
+}
+
+impl CartridgeHeader {
+    /// Parse and validate the header of the rom
+    pub fn parse(rom: &[Byte]) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated {
+                expected: HEADER_END,
+                actual: rom.len(),
+            });
+        }
+        let byte = |address: Address| rom[address as usize];
+
+        let cgb_flag = byte(CGB_FLAG_ADDRESS);
+        let cgb_support = match cgb_flag {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // color era titles are shortened to make room for the manufacturer code
+        let manufacturer = &rom[MANUFACTURER_ADDRESS as usize..CGB_FLAG_ADDRESS as usize];
+        let manufacturer_code = if cgb_support != CgbSupport::None
+            && manufacturer.iter().all(|c| c.is_ascii_uppercase())
+        {
+            Some(String::from_utf8_lossy(manufacturer).into_owned())
+        } else {
+            None
+        };
+        let title_end = match (cgb_support, &manufacturer_code) {
+            (_, Some(_)) => MANUFACTURER_ADDRESS,
+            (CgbSupport::None, None) => CGB_FLAG_ADDRESS + 1,
+            _ => CGB_FLAG_ADDRESS,
+        };
+        let title = rom[TITLE_ADDRESS as usize..title_end as usize]
+            .iter()
+            .take_while(|c| **c != 0)
+            .map(|c| *c as char)
+            .collect();
+
+        let header = Self {
+            title,
+            manufacturer_code,
+            cgb_support,
+            sgb_support: byte(SGB_FLAG_ADDRESS) == 0x03,
+            new_licensee_code: String::from_utf8_lossy(
+                &rom[NEW_LICENSEE_ADDRESS as usize..SGB_FLAG_ADDRESS as usize],
+            )
+            .into_owned(),
+            old_licensee_code: byte(OLD_LICENSEE_ADDRESS),
+            cartridge_type: byte(CARTRIDGE_TYPE_ADDRESS),
+            rom_size: byte(ROM_SIZE_ADDRESS),
+            ram_size: byte(RAM_SIZE_ADDRESS),
+            destination: if byte(DESTINATION_ADDRESS) == 0 {
+                Destination::Japan
+            } else {
+                Destination::Overseas
+            },
+            version: byte(VERSION_ADDRESS),
+            header_checksum: byte(HEADER_CHECKSUM_ADDRESS),
+            global_checksum: ((byte(GLOBAL_CHECKSUM_ADDRESS) as Word) << 8)
+                | byte(GLOBAL_CHECKSUM_ADDRESS + 1) as Word,
+        };
+
+        let actual = Self::compute_header_checksum(rom);
+        if actual != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksum {
+                expected: header.header_checksum,
+                actual,
+            });
+        }
+
+        let expected = header.rom_banks()? * ROM_BANK_SIZE;
+        if rom.len() < expected {
+            return Err(CartridgeError::Truncated {
+                expected,
+                actual: rom.len(),
+            });
+        }
+        header.ram_banks()?;
+
+        Ok(header)
+    }
+
+    /// Checksum over 0x0134-0x014C, which is verified by the boot rom
+    pub fn compute_header_checksum(rom: &[Byte]) -> Byte {
+        rom[TITLE_ADDRESS as usize..=VERSION_ADDRESS as usize]
+            .iter()
+            .fold(0, |checksum: Byte, b| {
+                checksum.wrapping_sub(*b).wrapping_sub(1)
+            })
+    }
+
+    /// Sum of every rom byte except the global checksum itself, not verified by hardware
+    pub fn compute_global_checksum(rom: &[Byte]) -> Word {
+        let checksum = GLOBAL_CHECKSUM_ADDRESS as usize..GLOBAL_CHECKSUM_ADDRESS as usize + 2;
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| !checksum.contains(i))
+            .fold(0, |sum: Word, (_, b)| sum.wrapping_add(*b as Word))
+    }
+
+    /// Number of 16 KiB rom banks
+    pub fn rom_banks(&self) -> Result<usize, CartridgeError> {
+        match self.rom_size {
+            0x00..=0x08 => Ok(2 << self.rom_size),
+            code => Err(CartridgeError::UnsupportedRomSize(code)),
+        }
+    }
+
+    /// Number of 8 KiB ram banks
+    pub fn ram_banks(&self) -> Result<usize, CartridgeError> {
+        match self.ram_size {
+            // 0x01 was never used by any cartridge
+            0x00 | 0x01 => Ok(0),
+            0x02 => Ok(1),
+            0x03 => Ok(4),
+            0x04 => Ok(16),
+            0x05 => Ok(8),
+            code => Err(CartridgeError::UnsupportedRamSize(code)),
+        }
+    }
+
+    /// Check if the cartridge ram is battery backed
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E
+        )
+    }
+
+    /// Check if the cartridge has an MBC3 real time clock
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0F | 0x10)
+    }
+
+    /// Check if the cartridge has a rumble motor
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.cartridge_type, 0x1C..=0x1E)
+    }
+
+    /// Licensee code, using the new code if the old one defers to it
+    pub fn licensee(&self) -> String {
+        if self.old_licensee_code == 0x33 {
+            self.new_licensee_code.clone()
+        } else {
+            format!("{:02X}", self.old_licensee_code)
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    audio, clock, cpu, graphics, joypad,
+    memory::{self, Memory},
+    utils::{Address, Byte},
+};
+
+pub const IO_ADDRESS: Address = 0xFF00;
+pub const IO_END_ADDRESS: Address = 0xFF7F;
+pub const IO_SIZE: usize = 0x80;
+
+/// I/O register contents left behind by the DMG boot rom, DIV is set by the clock
+/// and the other models only differ in a few registers
+pub const POST_BOOT_IO: &[(Address, Byte)] = &[
+    (0xFF00, 0xCF), // P1
+    (0xFF01, 0x00), // SB
+    (0xFF02, 0x7E), // SC
+    (0xFF05, 0x00), // TIMA
+    (0xFF06, 0x00), // TMA
+    (0xFF07, 0xF8), // TAC
+    (0xFF0F, 0xE1), // IF
+    (0xFF10, 0x80), // NR10
+    (0xFF11, 0xBF), // NR11
+    (0xFF12, 0xF3), // NR12
+    (0xFF13, 0xFF), // NR13
+    (0xFF14, 0xBF), // NR14
+    (0xFF16, 0x3F), // NR21
+    (0xFF17, 0x00), // NR22
+    (0xFF18, 0xFF), // NR23
+    (0xFF19, 0xBF), // NR24
+    (0xFF1A, 0x7F), // NR30
+    (0xFF1B, 0xFF), // NR31
+    (0xFF1C, 0x9F), // NR32
+    (0xFF1D, 0xFF), // NR33
+    (0xFF1E, 0xBF), // NR34
+    (0xFF20, 0xFF), // NR41
+    (0xFF21, 0x00), // NR42
+    (0xFF22, 0x00), // NR43
+    (0xFF23, 0xBF), // NR44
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF26, 0xF1), // NR52
+    (0xFF40, 0x91), // LCDC
+    (0xFF41, 0x85), // STAT
+    (0xFF42, 0x00), // SCY
+    (0xFF43, 0x00), // SCX
+    (0xFF44, 0x00), // LY
+    (0xFF45, 0x00), // LYC
+    (0xFF46, 0xFF), // DMA
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF4A, 0x00), // WY
+    (0xFF4B, 0x00), // WX
+];
+
+/// Cpu side semantics of a memory mapped I/O register (0xFF00-0xFF7F)
+#[derive(Debug, Clone, Copy)]
+pub struct IORegister {
+    pub address: Address,
+    /// Bits that can be read back, the others read as 1
+    pub read_mask: Byte,
+    /// Bits the cpu can write, the others keep their value
+    pub write_mask: Byte,
+    /// Side effect after the cpu writes the register, called with the written byte
+    pub on_write: Option<fn(&mut Memory, Byte)>,
+}
+
+impl IORegister {
+    pub const fn new(address: Address, read_mask: Byte, write_mask: Byte) -> Self {
+        Self {
+            address,
+            read_mask,
+            write_mask,
+            on_write: None,
+        }
+    }
+
+    /// Register where every bit can be read and written
+    pub const fn read_write(address: Address) -> Self {
+        Self::new(address, 0xFF, 0xFF)
+    }
+
+    /// Address without a register, reads 0xFF and ignores writes
+    pub const fn unmapped(address: Address) -> Self {
+        Self::new(address, 0x00, 0x00)
+    }
+
+    pub const fn with_write(self, on_write: fn(&mut Memory, Byte)) -> Self {
+        Self {
+            on_write: Some(on_write),
+            ..self
+        }
+    }
+}
+
+/// Build the register lookup table from the registers owned by each component
+pub fn io_registers() -> [IORegister; IO_SIZE] {
+    let mut table: [IORegister; IO_SIZE] =
+        std::array::from_fn(|i| IORegister::unmapped(IO_ADDRESS + i as Address));
+
+    for register in joypad::IO_REGISTERS
+        .iter()
+        .chain(memory::IO_REGISTERS.iter())
+        .chain(clock::IO_REGISTERS.iter())
+        .chain(cpu::IO_REGISTERS.iter())
+        .chain(audio::IO_REGISTERS.iter())
+        .chain(graphics::IO_REGISTERS.iter())
+    {
+        table[(register.address - IO_ADDRESS) as usize] = *register;
+    }
+    table
+}
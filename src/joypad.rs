@@ -4,6 +4,7 @@ use sdl2::keyboard::Keycode;
 
 use crate::{
     cpu::{INTERRUPT_FLAG_ADDRESS, JOYPAD_FLAG},
+    io::IORegister,
     memory::Memory,
     utils::{get_flag, set_flag, Address, Byte},
 };
@@ -22,6 +23,9 @@ pub const B_BUTTON: Byte = 0b1101_1101;
 pub const SELECT_BUTTON: Byte = 0b1101_1011;
 pub const START_BUTTON: Byte = 0b1101_0111;
 
+/// Only the select bits are writable, the button bits are read only
+pub const IO_REGISTERS: &[IORegister] = &[IORegister::new(JOYPAD_REGISTER_ADDRESS, 0x3F, 0x30)];
+
 pub struct Joypad {
     last_keys: HashSet<Keycode>,
     code_keys: HashMap<Byte, Keycode>,
@@ -46,7 +50,7 @@ impl Joypad {
 
     /// Update button register
     pub fn update(&mut self, memory: &mut Memory) {
-        let joypad_flags = memory.read_byte(JOYPAD_REGISTER_ADDRESS);
+        let joypad_flags = memory.read_io(JOYPAD_REGISTER_ADDRESS);
         let new_flags = if !get_flag(joypad_flags, DPAD_FLAG) {
             let mut flag = joypad_flags | 0xF;
             for dpad in [UP_BUTTON, DOWN_BUTTON, LEFT_BUTTON, RIGHT_BUTTON] {
@@ -66,12 +70,12 @@ impl Joypad {
         } else {
             joypad_flags | 0xF
         };
-        memory.write_byte(JOYPAD_REGISTER_ADDRESS, new_flags);
+        memory.write_io(JOYPAD_REGISTER_ADDRESS, new_flags);
     }
 
     /// Handle button press
     pub fn handle_button(&mut self, keycode: Keycode, down: bool, memory: &mut Memory) {
-        let joypad_flags = memory.read_byte(JOYPAD_REGISTER_ADDRESS);
+        let joypad_flags = memory.read_io(JOYPAD_REGISTER_ADDRESS);
         match keycode {
             Keycode::A | Keycode::W | Keycode::D | Keycode::S => {
                 if down {
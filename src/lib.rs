@@ -1,10 +1,18 @@
+pub mod asm;
 pub mod audio;
+pub mod boot;
+pub mod cheat;
 pub mod clock;
 pub mod cpu;
+pub mod disasm;
 pub mod gb;
 pub mod graphics;
+pub mod header;
+pub mod io;
 pub mod joypad;
 pub mod memory;
+pub mod model;
+pub mod patch;
 pub mod utils;
 
 mod test;
@@ -1,8 +1,16 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 use clap::{App, Arg};
-use gb_rs::gb::GameBoy;
-use log::{debug, info};
+use gb_rs::{
+    boot::BOOT_ROM,
+    gb::GameBoy,
+    model::Model,
+    patch::{apply_patch, find_patch},
+};
+use log::{debug, error, info};
 
 fn main() -> Result<(), String> {
     env_logger::init();
@@ -19,13 +27,84 @@ fn main() -> Result<(), String> {
                 .takes_value(true)
                 .required(true),
         )
+        .arg(
+            Arg::with_name("patch")
+                .short('p')
+                .long("patch")
+                .value_name("PATCH")
+                .help("Applies an IPS, UPS or BPS patch, defaults to a patch next to the ROM file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cheat")
+                .short('c')
+                .long("cheat")
+                .value_name("CODE")
+                .help("Adds a Game Genie (ABC-DEF-GHI) or GameShark (01VVAAAA) code, can be repeated")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::with_name("cheat_file")
+                .long("cheats")
+                .value_name("CHEATS")
+                .help("Adds the cheat codes of a file, one code per line, C toggles them while running")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("boot_bin")
                 .short('b')
                 .long("boot binary")
                 .value_name("BOOT")
-                .help("Sets the Boot ROM file to read")
-                .default_value(Path::new("assets").join("dmg_boot.bin").to_str().unwrap()),
+                .help("Sets the Boot ROM file to read, defaults to the built in open boot ROM on DMG. Other models skip the boot ROM without one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("model")
+                .short('m')
+                .long("model")
+                .value_name("MODEL")
+                .help("Sets the hardware model, which decides the state after the boot ROM")
+                .possible_values(Model::NAMES)
+                .ignore_case(true)
+                .default_value("dmg"),
+        )
+        .arg(
+            Arg::with_name("skip_boot")
+                .long("skip-boot")
+                .help("Starts the ROM at 0x100 without running a boot ROM")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("save_file")
+                .short('s')
+                .long("save")
+                .value_name("SAVE")
+                .help("Sets the battery save file, defaults to the ROM file with .sav extension")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no_save")
+                .long("no-save")
+                .help("Disables loading and writing battery save files")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("no_ppu_restrictions")
+                .long("no-ppu-restrictions")
+                .help("Allows VRAM and OAM access in every PPU mode, for debugging")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("trace_file")
+                .long("trace")
+                .value_name("TRACE")
+                .help("Writes the cpu state before every instruction to a file, in the gameboy-doctor format. Implies --skip-boot and makes LY read 0x90")
+                .takes_value(true)
+                .conflicts_with("boot_bin"),
         )
         .arg(
             Arg::with_name("no_graphics")
@@ -43,21 +122,39 @@ fn main() -> Result<(), String> {
         )
         .get_matches();
 
-    let boot_bin = matches.value_of("boot_bin").unwrap();
-    info!("Loading boot bin {}", boot_bin);
-    let contents = fs::read(boot_bin);
-    let boot_bin = match contents {
-        Ok(fs) => fs,
-        Err(e) => {
-            debug!("Unable to read file {} due to {}", boot_bin, e.to_string());
-            return Err(String::from("Unable to read file"));
+    let model: Model = matches.value_of("model").unwrap().parse()?;
+    info!("Emulating model {}", model);
+
+    // gameboy-doctor logs start at 0x100 with the post boot state
+    let skip_boot = matches.is_present("skip_boot") || matches.is_present("trace_file");
+    let boot_bin = if skip_boot {
+        None
+    } else if let Some(boot_bin) = matches.value_of("boot_bin") {
+        info!("Loading boot bin {}", boot_bin);
+        let contents = fs::read(boot_bin);
+        match contents {
+            Ok(fs) => Some(fs),
+            Err(e) => {
+                debug!("Unable to read file {} due to {}", boot_bin, e.to_string());
+                return Err(String::from("Unable to read file"));
+            }
         }
+    } else if model == Model::DMG {
+        info!("Using built in boot rom");
+        Some(BOOT_ROM.to_vec())
+    } else {
+        info!("No built in boot rom for {}, skipping the boot rom", model);
+        None
     };
 
     let rom_file = matches.value_of("rom_file").unwrap();
+    let save_file = match matches.value_of("save_file") {
+        Some(save_file) => PathBuf::from(save_file),
+        None => Path::new(rom_file).with_extension("sav"),
+    };
     info!("Running rom file {}", rom_file);
     let contents = fs::read(rom_file);
-    let rom_file = match contents {
+    let rom = match contents {
         Ok(fs) => fs,
         Err(e) => {
             debug!("Unable to read file {} due to {}", rom_file, e.to_string());
@@ -65,12 +162,83 @@ fn main() -> Result<(), String> {
         }
     };
 
+    let patch_file = match matches.value_of("patch") {
+        Some(patch_file) => Some(PathBuf::from(patch_file)),
+        None => find_patch(Path::new(rom_file)),
+    };
+    let rom = match patch_file {
+        Some(patch_file) => {
+            info!("Applying patch file {}", patch_file.display());
+            let patch = match fs::read(&patch_file) {
+                Ok(fs) => fs,
+                Err(e) => {
+                    debug!(
+                        "Unable to read file {} due to {}",
+                        patch_file.display(),
+                        e.to_string()
+                    );
+                    return Err(String::from("Unable to read file"));
+                }
+            };
+            match apply_patch(&rom, &patch) {
+                Ok(patched) => patched,
+                Err(e) => {
+                    error!("Unable to apply patch: {}", e);
+                    return Err(e.to_string());
+                }
+            }
+        }
+        None => rom,
+    };
+
     let graphics_enabled = !matches.is_present("no_graphics");
     let audio_enabled = !matches.is_present("no_audio");
 
-    let mut gameboy = GameBoy::new(graphics_enabled, audio_enabled);
-    gameboy.load_boot(boot_bin);
-    gameboy.load_rom(rom_file);
+    let mut gameboy = GameBoy::new(model, graphics_enabled, audio_enabled);
+    gameboy.set_ppu_restrictions(!matches.is_present("no_ppu_restrictions"));
+    if let Err(e) = gameboy.load_rom(rom) {
+        error!("Unable to load rom: {}", e);
+        return Err(e.to_string());
+    }
+    // the post boot state depends on the cartridge header
+    match boot_bin {
+        Some(boot_bin) => gameboy.load_boot(boot_bin),
+        None => gameboy.skip_boot(),
+    }
+    if let Some(trace_file) = matches.value_of("trace_file") {
+        info!("Tracing cpu to {}", trace_file);
+        if let Err(e) = gameboy.set_trace_file(Path::new(trace_file)) {
+            error!("Unable to create trace file {}: {}", trace_file, e);
+            return Err(e.to_string());
+        }
+    }
+    if let Some(cheat_file) = matches.value_of("cheat_file") {
+        info!("Loading cheat file {}", cheat_file);
+        let contents = match fs::read_to_string(cheat_file) {
+            Ok(fs) => fs,
+            Err(e) => {
+                debug!(
+                    "Unable to read file {} due to {}",
+                    cheat_file,
+                    e.to_string()
+                );
+                return Err(String::from("Unable to read file"));
+            }
+        };
+        if let Err(e) = gameboy.load_cheats(&contents) {
+            error!("Unable to load cheats: {}", e);
+            return Err(e.to_string());
+        }
+    }
+    for code in matches.values_of("cheat").into_iter().flatten() {
+        if let Err(e) = gameboy.add_cheat(code) {
+            error!("Unable to add cheat: {}", e);
+            return Err(e.to_string());
+        }
+    }
+    if !matches.is_present("no_save") {
+        gameboy.load_save(save_file);
+    }
     gameboy.run();
 
     Ok(())
@@ -1,26 +1,86 @@
-use log::info;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
 
 use crate::{
-    graphics::OAM_ADDRESS,
-    utils::{address2string, bytes2word, Address, Byte, Word},
+    cheat::Cheats,
+    clock::{Clock, CLOCK_FREQ},
+    graphics::{LY_ADDRESS, OAM_ADDRESS},
+    header::{CartridgeError, CartridgeHeader},
+    io::{io_registers, IORegister, IO_ADDRESS, IO_END_ADDRESS, IO_SIZE, POST_BOOT_IO},
+    joypad::JOYPAD_REGISTER_ADDRESS,
+    model::Model,
+    utils::{bytes2word, Address, Byte, Word},
 };
 
 const BOOTROM_SIZE: usize = 0x100;
 const MEMORY_SIZE: usize = 0x10000;
 const ROM_SIZE: usize = 0x4000;
+const RAM_SIZE: usize = 0x2000;
+const MBC2_RAM_SIZE: usize = 0x200;
+
+const ROM_BANK_ADDRESS: Address = 0x4000;
+const VRAM_ADDRESS: Address = 0x8000;
+const RAM_ADDRESS: Address = 0xA000;
 
 const DMA_ADDRESS: Address = 0xFF46;
-const MBC_TYPE_ADDRESS: Address = 0x0147;
-const ROM_SIZE_ADDRESS: Address = 0x0148;
-const RAM_SIZE_ADDRESS: Address = 0x0149;
+/// Bytes copied by an OAM DMA transfer, one per m-cycle
+const DMA_SIZE: Word = 0xA0;
+/// M-cycles between the DMA register write and the first copied byte
+const DMA_DELAY: u8 = 1;
+const WORK_RAM_ADDRESS: Address = 0xC000;
+/// Mirror of work ram 0xC000-0xDDFF
+const ECHO_ADDRESS: Address = 0xE000;
+const ECHO_END_ADDRESS: Address = 0xFDFF;
+const ECHO_OFFSET: Address = 0x2000;
+const OAM_END_ADDRESS: Address = 0xFE9F;
+/// Prohibited area after OAM
+const UNUSABLE_ADDRESS: Address = 0xFEA0;
+const UNUSABLE_END_ADDRESS: Address = 0xFEFF;
+const HRAM_ADDRESS: Address = 0xFF80;
+
+const LCDC_ADDRESS: Address = 0xFF40;
+const LCDC_ENABLE_FLAG: Byte = 0b1000_0000;
+const LCD_STATUS_ADDRESS: Address = 0xFF41;
+const PPU_MODE_MASK: Byte = 0b11;
+const NINTENDO_LOGO_ADDRESS: Address = 0x0104;
 
 const UNLOAD_BOOT_ADDRESS: Address = 0xFF50;
+const SERIAL_DATA_ADDRESS: Address = 0xFF01;
+const SERIAL_CONTROL_ADDRESS: Address = 0xFF02;
+const SOUND_CONTROL_ADDRESS: Address = 0xFF26;
+/// LY read by the cpu while stubbed, gameboy-doctor reference logs are made with LY stuck at the start of VBlank
+const STUB_LY: Byte = 0x90;
+
+pub const IO_REGISTERS: &[IORegister] = &[
+    IORegister::read_write(SERIAL_DATA_ADDRESS),
+    IORegister::new(SERIAL_CONTROL_ADDRESS, 0x81, 0x81),
+    IORegister::read_write(DMA_ADDRESS).with_write(Memory::start_dma),
+    IORegister::unmapped(UNLOAD_BOOT_ADDRESS).with_write(Memory::unload_boot),
+];
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum CartridgeType {
     None,
     RomOnly,
     MBC1,
+    MBC2,
+    MBC3,
+    MBC5,
+}
+
+impl CartridgeType {
+    /// Get the cartridge type from the header type code
+    pub fn from_header(header: &CartridgeHeader) -> Result<Self, CartridgeError> {
+        match header.cartridge_type {
+            0x00 => Ok(Self::RomOnly),
+            0x01..=0x03 => Ok(Self::MBC1),
+            0x05 | 0x06 => Ok(Self::MBC2),
+            0x0F..=0x13 => Ok(Self::MBC3),
+            0x19..=0x1E => Ok(Self::MBC5),
+            code => Err(CartridgeError::UnsupportedType(code)),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -28,6 +88,9 @@ pub enum CartridgeState {
     None,
     RomOnly(RomState),
     MBC1(MBC1State),
+    MBC2(MBC2State),
+    MBC3(MBC3State),
+    MBC5(MBC5State),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -36,26 +99,434 @@ pub struct RomState {}
 #[derive(Debug, PartialEq, Eq)]
 pub struct MBC1State {
     ram_enabled: bool,
+    /// Lower 5 bits of the rom bank number (BANK1)
     rom_number: usize,
+    /// 2 bit register used as ram bank or upper rom bank bits (BANK2)
     ram_number: usize,
+    /// Banking mode, false is simple mode, true is advanced mode
+    advanced_mode: bool,
+    /// Multicart (MBC1M) wiring, where BANK1 only has 4 bits connected
+    multicart: bool,
 }
 
 impl MBC1State {
+    fn new(multicart: bool) -> Self {
+        Self {
+            rom_number: 1,
+            ram_enabled: false,
+            ram_number: 0,
+            advanced_mode: false,
+            multicart,
+        }
+    }
+
+    /// Write to the MBC1 registers (0x0000-0x7FFF)
+    fn write_register(&mut self, address: Address, byte: Byte) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = byte & 0xF == 0xA,
+            0x2000..=0x3FFF => {
+                // bank 0 cannot be selected, checked using all 5 bits
+                self.rom_number = match byte & 0x1F {
+                    0 => 1,
+                    n => n as usize,
+                };
+            }
+            0x4000..=0x5FFF => self.ram_number = (byte & 0b11) as usize,
+            0x6000..=0x7FFF => self.advanced_mode = byte & 1 == 1,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Number of bits shifted for the upper bank bits
+    fn bank2_shift(&self) -> usize {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+
+    /// Rom bank mapped to 0x0000-0x3FFF
+    fn rom_bank_low(&self, bank_count: usize) -> usize {
+        if self.advanced_mode {
+            (self.ram_number << self.bank2_shift()) % bank_count
+        } else {
+            0
+        }
+    }
+
+    /// Rom bank mapped to 0x4000-0x7FFF
+    fn rom_bank_high(&self, bank_count: usize) -> usize {
+        let bank1 = if self.multicart {
+            self.rom_number & 0xF
+        } else {
+            self.rom_number
+        };
+        ((self.ram_number << self.bank2_shift()) | bank1) % bank_count
+    }
+
+    /// Ram bank mapped to 0xA000-0xBFFF
+    fn ram_bank(&self, bank_count: usize) -> usize {
+        if self.advanced_mode {
+            self.ram_number % bank_count
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MBC2State {
+    ram_enabled: bool,
+    rom_number: usize,
+}
+
+impl MBC2State {
     fn new() -> Self {
         Self {
+            ram_enabled: false,
             rom_number: 1,
+        }
+    }
+
+    /// Write to the MBC2 registers (0x0000-0x3FFF), bit 8 of the address selects the register
+    fn write_register(&mut self, address: Address, byte: Byte) {
+        if address >= ROM_BANK_ADDRESS {
+            return;
+        }
+        if address & 0x100 == 0 {
+            self.ram_enabled = byte & 0xF == 0xA;
+        } else {
+            self.rom_number = match byte & 0xF {
+                0 => 1,
+                n => n as usize,
+            };
+        }
+    }
+
+    /// Rom bank mapped to 0x4000-0x7FFF
+    fn rom_bank_high(&self, bank_count: usize) -> usize {
+        self.rom_number % bank_count
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MBC3State {
+    ram_enabled: bool,
+    rom_number: usize,
+    /// Ram bank (0x00-0x03) or rtc register (0x08-0x0C)
+    ram_number: usize,
+    /// Last value written to the latch register
+    latch: Byte,
+    rtc: Option<RealTimeClock>,
+}
+
+impl MBC3State {
+    fn new(has_rtc: bool) -> Self {
+        Self {
             ram_enabled: false,
+            rom_number: 1,
             ram_number: 0,
+            latch: 0xFF,
+            rtc: if has_rtc {
+                Some(RealTimeClock::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Write to the MBC3 registers (0x0000-0x7FFF)
+    fn write_register(&mut self, address: Address, byte: Byte) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = byte & 0xF == 0xA,
+            0x2000..=0x3FFF => {
+                self.rom_number = match byte & 0x7F {
+                    0 => 1,
+                    n => n as usize,
+                };
+            }
+            0x4000..=0x5FFF => self.ram_number = byte as usize,
+            0x6000..=0x7FFF => {
+                // writing 0x00 then 0x01 latches the clock
+                if self.latch == 0x00 && byte == 0x01 {
+                    if let Some(ref mut rtc) = self.rtc {
+                        rtc.latch();
+                    }
+                }
+                self.latch = byte;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Rom bank mapped to 0x4000-0x7FFF
+    fn rom_bank_high(&self, bank_count: usize) -> usize {
+        self.rom_number % bank_count
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MBC5State {
+    ram_enabled: bool,
+    /// 9 bit rom bank number, bank 0 is allowed
+    rom_number: usize,
+    ram_number: usize,
+    /// Rumble carts use bit 3 of the ram bank register for the motor
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5State {
+    fn new(has_rumble: bool) -> Self {
+        Self {
+            ram_enabled: false,
+            rom_number: 1,
+            ram_number: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+
+    /// Write to the MBC5 registers (0x0000-0x5FFF)
+    fn write_register(&mut self, address: Address, byte: Byte) {
+        match address {
+            0x0000..=0x1FFF => self.ram_enabled = byte == 0x0A,
+            0x2000..=0x2FFF => self.rom_number = (self.rom_number & 0x100) | byte as usize,
+            0x3000..=0x3FFF => {
+                self.rom_number = (self.rom_number & 0xFF) | (((byte & 1) as usize) << 8)
+            }
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = byte & 0b1000 != 0;
+                    self.ram_number = (byte & 0b0111) as usize;
+                } else {
+                    self.ram_number = (byte & 0xF) as usize;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Rom bank mapped to 0x4000-0x7FFF
+    fn rom_bank_high(&self, bank_count: usize) -> usize {
+        self.rom_number % bank_count
+    }
+}
+
+/// MBC3 real time clock, advanced by emulated machine cycles
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RealTimeClock {
+    seconds: Byte,
+    minutes: Byte,
+    hours: Byte,
+    /// 9 bit day counter
+    days: Word,
+    halt: bool,
+    /// Day counter overflow
+    carry: bool,
+    /// Registers seen by the cpu, in the order S, M, H, DL, DH
+    latched: [Byte; 5],
+    /// Machine cycles since the last second
+    cycles: u32,
+}
+
+impl RealTimeClock {
+    /// Machine cycles per second
+    const SECOND_CYCLES: u32 = CLOCK_FREQ / 4;
+    const DH_DAY_FLAG: Byte = 0b0000_0001;
+    const DH_HALT_FLAG: Byte = 0b0100_0000;
+    const DH_CARRY_FLAG: Byte = 0b1000_0000;
+    /// Size of the rtc footer in a save file
+    pub const SAVE_SIZE: usize = 48;
+
+    fn new() -> Self {
+        Self {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halt: false,
+            carry: false,
+            latched: [0; 5],
+            cycles: 0,
+        }
+    }
+
+    /// Advance the clock by mcycles
+    fn tick(&mut self, mcycles: u8) {
+        if self.halt {
+            return;
+        }
+        self.cycles += mcycles as u32;
+        while self.cycles >= Self::SECOND_CYCLES {
+            self.cycles -= Self::SECOND_CYCLES;
+            self.increment_second();
+        }
+    }
+
+    /// Increment by one second, out of range values count up to their bit width without carry
+    fn increment_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days += 1;
+        if self.days > 0x1FF {
+            self.days = 0;
+            self.carry = true;
+        }
+    }
+
+    fn registers(&self) -> [Byte; 5] {
+        let mut dh = (self.days >> 8) as Byte & Self::DH_DAY_FLAG;
+        if self.halt {
+            dh |= Self::DH_HALT_FLAG;
+        }
+        if self.carry {
+            dh |= Self::DH_CARRY_FLAG;
+        }
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as Byte,
+            dh,
+        ]
+    }
+
+    fn latch(&mut self) {
+        self.latched = self.registers();
+    }
+
+    /// Read latched register 0x08-0x0C
+    fn read(&self, register: usize) -> Byte {
+        self.latched[register - 0x08]
+    }
+
+    /// Write register 0x08-0x0C, which also updates the latched value
+    fn write(&mut self, register: usize, byte: Byte) {
+        match register {
+            0x08 => {
+                self.seconds = byte & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.minutes = byte & 0x3F,
+            0x0A => self.hours = byte & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | byte as Word,
+            0x0C => {
+                self.days = (self.days & 0xFF) | (((byte & Self::DH_DAY_FLAG) as Word) << 8);
+                self.halt = byte & Self::DH_HALT_FLAG != 0;
+                self.carry = byte & Self::DH_CARRY_FLAG != 0;
+            }
+            _ => unreachable!(),
+        }
+        self.latched[register - 0x08] = self.registers()[register - 0x08];
+    }
+
+    /// Serialize to the 48 byte footer used by other emulators, each register as a
+    /// little endian u32, current then latched, followed by a 64 bit unix timestamp
+    pub fn to_bytes(&self) -> Vec<Byte> {
+        let mut data = Vec::with_capacity(Self::SAVE_SIZE);
+        for reg in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*reg as u32).to_le_bytes());
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_secs())
+            .unwrap_or(0);
+        data.extend_from_slice(&timestamp.to_le_bytes());
+        data
+    }
+
+    /// Deserialize from the save footer, the timestamp is ignored so time only
+    /// advances while emulating
+    pub fn from_bytes(data: &[Byte]) -> Option<Self> {
+        if data.len() < 40 {
+            return None;
+        }
+        let reg = |i: usize| data[i * 4];
+        let mut rtc = Self::new();
+        for register in 0x08..=0x0C {
+            rtc.write(register, reg(register - 0x08));
         }
+        for i in 0..5 {
+            rtc.latched[i] = reg(i + 5);
+        }
+        Some(rtc)
+    }
+}
+
+/// OAM DMA transfer in progress
+#[derive(Debug, Clone, Copy)]
+struct DmaTransfer {
+    source: Address,
+    /// Number of bytes copied so far
+    index: Word,
+}
+
+/// OAM DMA transfer waiting for its start up delay
+#[derive(Debug, Clone, Copy)]
+struct DmaStart {
+    source: Address,
+    delay: u8,
+}
+
+/// Memory as the cpu sees it, where every read or write of an instruction takes a machine cycle
+pub trait Bus {
+    /// Read a byte without spending a machine cycle
+    fn read_byte(&self, address: Address) -> Byte;
+
+    /// Write a byte without spending a machine cycle
+    fn write_byte(&mut self, address: Address, byte: Byte);
+
+    /// Spend machine cycles without a bus access
+    fn tick(&mut self, clock: &mut Clock, mcycles: u8);
+
+    /// Read a byte, which takes one machine cycle
+    fn read_cycle(&mut self, clock: &mut Clock, address: Address) -> Byte {
+        self.tick(clock, 1);
+        self.read_byte(address)
+    }
+
+    /// Write a byte, which takes one machine cycle
+    fn write_cycle(&mut self, clock: &mut Clock, address: Address, byte: Byte) {
+        self.tick(clock, 1);
+        self.write_byte(address, byte);
     }
 }
 
 pub struct Memory {
     memory: [Byte; MEMORY_SIZE],
     boot_rom: [Byte; BOOTROM_SIZE],
+    boot_mapped: bool,
     rom: Vec<Vec<Byte>>,
     ram: Vec<Vec<Byte>>,
     cartridge: CartridgeState,
+    header: Option<CartridgeHeader>,
+    /// Cartridge ram was written since the last save
+    ram_dirty: bool,
+    io_registers: [IORegister; IO_SIZE],
+    /// I/O registers written by the cpu, one bit per register
+    io_written: u128,
+    dma: Option<DmaTransfer>,
+    dma_start: Option<DmaStart>,
+    /// Block cpu VRAM and OAM access while the PPU uses them
+    ppu_restrictions: bool,
+    /// The cpu reads LY as STUB_LY, for gameboy-doctor traces
+    ly_stub: bool,
+    cheats: Cheats,
 }
 
 impl Memory {
@@ -63,82 +534,356 @@ impl Memory {
         Memory {
             memory: [0; MEMORY_SIZE],
             boot_rom: [0; BOOTROM_SIZE],
+            boot_mapped: false,
             rom: Vec::new(),
             ram: Vec::new(),
             cartridge: CartridgeState::None,
+            header: None,
+            ram_dirty: false,
+            io_registers: io_registers(),
+            io_written: 0,
+            dma: None,
+            dma_start: None,
+            ppu_restrictions: true,
+            ly_stub: false,
+            cheats: Cheats::default(),
         }
     }
 
-    pub fn load_cartidge(&mut self, rom_data: Vec<u8>) {
-        let ctype = self.get_cartridge_type_rom(&rom_data);
-        let rom_size = self.get_rom_size_rom(&rom_data);
-        let ram_size = self.get_ram_size_rom(&rom_data);
+    /// Load the rom into the cartridge slot, validating its header
+    pub fn load_cartidge(&mut self, rom_data: Vec<u8>) -> Result<(), CartridgeError> {
+        let header = CartridgeHeader::parse(&rom_data)?;
+        let ctype = CartridgeType::from_header(&header)?;
+        let rom_bank_num = header.rom_banks()?;
+        let ram_bank_num = header.ram_banks()?;
         info!("Load Rom Size {:#04X?}", rom_data.len(),);
+        info!("Title {:?}", header.title);
         info!("Rom Type {:?}", ctype);
-        info!("Rom Size {:?}", rom_size);
-        info!("Ram Size {:?}", ram_size);
+        info!("Rom Banks {:?}", rom_bank_num);
+        info!("Ram Banks {:?}", ram_bank_num);
+        if CartridgeHeader::compute_global_checksum(&rom_data) != header.global_checksum {
+            warn!("Global checksum mismatch");
+        }
+
+        // copy rom_data to self.rom
+        self.rom = rom_data
+            .chunks(ROM_SIZE)
+            .take(rom_bank_num)
+            .map(|bank| bank.to_vec())
+            .collect();
+
+        self.ram = if ctype == CartridgeType::MBC2 {
+            // built in 512x4 bit ram
+            vec![vec![0; MBC2_RAM_SIZE]]
+        } else {
+            vec![vec![0; RAM_SIZE]; ram_bank_num]
+        };
+        self.ram_dirty = false;
 
         self.cartridge = match ctype {
             CartridgeType::RomOnly => CartridgeState::RomOnly(RomState {}),
-            CartridgeType::MBC1 => CartridgeState::MBC1(MBC1State::new()),
-            CartridgeType::None => panic!("Unknown cartridge type"),
+            CartridgeType::MBC1 => CartridgeState::MBC1(MBC1State::new(self.is_multicart())),
+            CartridgeType::MBC2 => CartridgeState::MBC2(MBC2State::new()),
+            CartridgeType::MBC3 => CartridgeState::MBC3(MBC3State::new(header.has_rtc())),
+            CartridgeType::MBC5 => CartridgeState::MBC5(MBC5State::new(header.has_rumble())),
+            CartridgeType::None => unreachable!(),
         };
-
-        // copy rom_data to self.rom
-        let rom_data = rom_data.as_slice();
-
-        let rom_bank_num = 1 << (rom_size + 1);
-        for i in 0..rom_bank_num {
-            let mut rom_bank = Vec::with_capacity(ROM_SIZE);
-            rom_bank.extend_from_slice(&rom_data[ROM_SIZE * i..ROM_SIZE * (i + 1)]);
-            self.rom.push(rom_bank);
-        }
-        self.memory[BOOTROM_SIZE..ROM_SIZE].copy_from_slice(&self.rom[0][BOOTROM_SIZE..ROM_SIZE]);
-        self.memory[ROM_SIZE..ROM_SIZE * 2].copy_from_slice(&self.rom[1]);
+        self.header = Some(header);
+        Ok(())
     }
 
     pub fn load_boot(&mut self, boot_data: Vec<u8>) {
         info!("Boot Size {:#04X?}", boot_data.len());
         self.boot_rom.copy_from_slice(&boot_data);
-        self.memory[..BOOTROM_SIZE].copy_from_slice(&self.boot_rom);
+        self.boot_mapped = true;
     }
 
     pub fn read_byte(&self, address: Address) -> Byte {
-        let address = address as usize;
-        self.memory[address]
+        if self.dma_blocked(address) || self.ppu_blocked(address) {
+            return 0xFF;
+        }
+        match address {
+            0x0000..=0x7FFF if !self.is_boot_address(address) => {
+                self.cheats.patch_rom(address, self.read_cartridge(address))
+            }
+            0x0000..=0x7FFF | RAM_ADDRESS..=0xBFFF => self.read_cartridge(address),
+            ECHO_ADDRESS..=ECHO_END_ADDRESS => self.memory[(address - ECHO_OFFSET) as usize],
+            // DMG reads 0x00, or 0xFF while the PPU blocks OAM
+            UNUSABLE_ADDRESS..=UNUSABLE_END_ADDRESS => 0x00,
+            LY_ADDRESS if self.ly_stub => STUB_LY,
+            IO_ADDRESS..=IO_END_ADDRESS => {
+                let register = &self.io_registers[(address - IO_ADDRESS) as usize];
+                self.memory[address as usize] | !register.read_mask
+            }
+            _ => self.memory[address as usize],
+        }
     }
 
     pub fn read_word(&self, address: Address) -> Word {
-        let address = address as usize;
-        bytes2word(self.memory[address], self.memory[address + 1])
+        bytes2word(self.read_byte(address), self.read_byte(address + 1))
     }
 
     /// Write byte to address according to MMU
     pub fn write_byte(&mut self, address: Address, byte: Byte) {
+        if self.dma_blocked(address) || self.ppu_blocked(address) {
+            return;
+        }
         match address {
-            UNLOAD_BOOT_ADDRESS => self.unload_boot(),
-            DMA_ADDRESS => self.dma(byte),
-            _ => (),
+            0x0000..=0x7FFF | RAM_ADDRESS..=0xBFFF => self.write_cartridge(address, byte),
+            ECHO_ADDRESS..=ECHO_END_ADDRESS => {
+                self.memory[(address - ECHO_OFFSET) as usize] = byte;
+            }
+            UNUSABLE_ADDRESS..=UNUSABLE_END_ADDRESS => (),
+            IO_ADDRESS..=IO_END_ADDRESS => {
+                let register = self.io_registers[(address - IO_ADDRESS) as usize];
+                let old = self.memory[address as usize];
+                self.memory[address as usize] =
+                    (old & !register.write_mask) | (byte & register.write_mask);
+                self.io_written |= 1 << (address - IO_ADDRESS);
+                if let Some(on_write) = register.on_write {
+                    on_write(self, byte);
+                }
+            }
+            _ => self.memory[address as usize] = byte,
         }
+    }
 
-        let address = address as usize;
+    /// Read I/O register as the owning component, ignoring the cpu read mask
+    pub fn read_io(&self, address: Address) -> Byte {
+        assert!((IO_ADDRESS..=IO_END_ADDRESS).contains(&address));
+        self.memory[address as usize]
+    }
+
+    /// Write I/O register as the owning component, ignoring the cpu write mask and side effects
+    pub fn write_io(&mut self, address: Address, byte: Byte) {
+        assert!((IO_ADDRESS..=IO_END_ADDRESS).contains(&address));
+        self.memory[address as usize] = byte;
+    }
+
+    /// Check if the cpu wrote the I/O register since the last call, and reset the flag
+    pub fn take_io_written(&mut self, address: Address) -> bool {
+        let bit = 1 << (address - IO_ADDRESS);
+        let written = self.io_written & bit != 0;
+        self.io_written &= !bit;
+        written
+    }
+
+    /// Check if the address reads from the boot rom instead of the cartridge
+    fn is_boot_address(&self, address: Address) -> bool {
+        self.boot_mapped && (address as usize) < BOOTROM_SIZE
+    }
+
+    /// Read from the cartridge rom (0x0000-0x7FFF) or ram (0xA000-0xBFFF)
+    fn read_cartridge(&self, address: Address) -> Byte {
+        if self.is_boot_address(address) {
+            return self.boot_rom[address as usize];
+        }
+
+        let (bank, offset) = match &self.cartridge {
+            CartridgeState::None => return self.memory[address as usize],
+            CartridgeState::RomOnly(_) => match address {
+                0x0000..=0x3FFF => (0, address),
+                0x4000..=0x7FFF => (1, address - ROM_BANK_ADDRESS),
+                _ => return self.memory[address as usize],
+            },
+            CartridgeState::MBC1(state) => match address {
+                0x0000..=0x3FFF => (state.rom_bank_low(self.rom.len()), address),
+                0x4000..=0x7FFF => (
+                    state.rom_bank_high(self.rom.len()),
+                    address - ROM_BANK_ADDRESS,
+                ),
+                _ => {
+                    if !state.ram_enabled || self.ram.is_empty() {
+                        return 0xFF;
+                    }
+                    let bank = state.ram_bank(self.ram.len());
+                    return self.ram[bank][(address - RAM_ADDRESS) as usize];
+                }
+            },
+            CartridgeState::MBC2(state) => match address {
+                0x0000..=0x3FFF => (0, address),
+                0x4000..=0x7FFF => (
+                    state.rom_bank_high(self.rom.len()),
+                    address - ROM_BANK_ADDRESS,
+                ),
+                _ => {
+                    if !state.ram_enabled {
+                        return 0xFF;
+                    }
+                    // only the lower 9 bits are used, upper nibble is open bus
+                    let offset = (address - RAM_ADDRESS) as usize % MBC2_RAM_SIZE;
+                    return self.ram[0][offset] | 0xF0;
+                }
+            },
+            CartridgeState::MBC3(state) => match address {
+                0x0000..=0x3FFF => (0, address),
+                0x4000..=0x7FFF => (
+                    state.rom_bank_high(self.rom.len()),
+                    address - ROM_BANK_ADDRESS,
+                ),
+                _ => {
+                    if !state.ram_enabled {
+                        return 0xFF;
+                    }
+                    return match (state.ram_number, &state.rtc) {
+                        (0x00..=0x07, _) if !self.ram.is_empty() => {
+                            let bank = state.ram_number % self.ram.len();
+                            self.ram[bank][(address - RAM_ADDRESS) as usize]
+                        }
+                        (0x08..=0x0C, Some(rtc)) => rtc.read(state.ram_number),
+                        _ => 0xFF,
+                    };
+                }
+            },
+            CartridgeState::MBC5(state) => match address {
+                0x0000..=0x3FFF => (0, address),
+                0x4000..=0x7FFF => (
+                    state.rom_bank_high(self.rom.len()),
+                    address - ROM_BANK_ADDRESS,
+                ),
+                _ => {
+                    if !state.ram_enabled || self.ram.is_empty() {
+                        return 0xFF;
+                    }
+                    let bank = state.ram_number % self.ram.len();
+                    return self.ram[bank][(address - RAM_ADDRESS) as usize];
+                }
+            },
+        };
+        self.rom[bank][offset as usize]
+    }
 
-        let ctype = self.get_cartridge_type();
-        match ctype {
-            CartridgeType::RomOnly => {
-                if address >= 0x8000 {
-                    self.memory[address] = byte;
+    /// Write to the cartridge registers (0x0000-0x7FFF) or ram (0xA000-0xBFFF)
+    fn write_cartridge(&mut self, address: Address, byte: Byte) {
+        match &mut self.cartridge {
+            CartridgeState::None => self.memory[address as usize] = byte,
+            CartridgeState::RomOnly(_) => {
+                if address >= VRAM_ADDRESS {
+                    self.memory[address as usize] = byte;
                 }
             }
-            CartridgeType::MBC1 => {
-                if address >= 0x8000 {
-                    self.memory[address] = byte;
-                } else if address < 0x8000 {
-                    unimplemented!("{}", address2string(address as Address));
+            CartridgeState::MBC1(state) => {
+                if address < VRAM_ADDRESS {
+                    state.write_register(address, byte);
+                } else if state.ram_enabled && !self.ram.is_empty() {
+                    let bank = state.ram_bank(self.ram.len());
+                    self.ram[bank][(address - RAM_ADDRESS) as usize] = byte;
+                    self.ram_dirty = true;
                 }
             }
-            CartridgeType::None => {
-                self.memory[address] = byte;
+            CartridgeState::MBC2(state) => {
+                if address < VRAM_ADDRESS {
+                    state.write_register(address, byte);
+                } else if state.ram_enabled {
+                    let offset = (address - RAM_ADDRESS) as usize % MBC2_RAM_SIZE;
+                    self.ram[0][offset] = byte & 0xF;
+                    self.ram_dirty = true;
+                }
+            }
+            CartridgeState::MBC3(state) => {
+                if address < VRAM_ADDRESS {
+                    state.write_register(address, byte);
+                } else if state.ram_enabled {
+                    match (state.ram_number, &mut state.rtc) {
+                        (0x00..=0x07, _) if !self.ram.is_empty() => {
+                            let bank = state.ram_number % self.ram.len();
+                            self.ram[bank][(address - RAM_ADDRESS) as usize] = byte;
+                            self.ram_dirty = true;
+                        }
+                        (0x08..=0x0C, Some(rtc)) => {
+                            rtc.write(state.ram_number, byte);
+                            self.ram_dirty = true;
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            CartridgeState::MBC5(state) => {
+                if address < VRAM_ADDRESS {
+                    state.write_register(address, byte);
+                } else if state.ram_enabled && !self.ram.is_empty() {
+                    let bank = state.ram_number % self.ram.len();
+                    self.ram[bank][(address - RAM_ADDRESS) as usize] = byte;
+                    self.ram_dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Advance the cartridge real time clock, if there is one
+    pub fn tick_rtc(&mut self, mcycles: u8) {
+        if let CartridgeState::MBC3(MBC3State {
+            rtc: Some(ref mut rtc),
+            ..
+        }) = self.cartridge
+        {
+            rtc.tick(mcycles);
+        }
+    }
+
+    /// Check if the cartridge has battery backed ram that should be saved
+    pub fn has_battery(&self) -> bool {
+        self.header
+            .as_ref()
+            .is_some_and(|header| header.has_battery())
+    }
+
+    /// Get the header of the loaded cartridge
+    pub fn get_header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    /// Check if cartridge ram changed since the last call, and reset the flag
+    pub fn take_ram_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.ram_dirty)
+    }
+
+    /// Get the save file contents, the raw ram banks followed by the rtc footer if present
+    pub fn get_save_data(&self) -> Vec<Byte> {
+        let mut data = self.ram.concat();
+        if let Some(rtc_data) = self.get_rtc_data() {
+            data.extend(rtc_data);
+        }
+        data
+    }
+
+    /// Load save file contents into cartridge ram, ignoring data that doesn't fit
+    pub fn load_save_data(&mut self, data: &[Byte]) {
+        let mut chunks = data.chunks(self.ram.first().map_or(RAM_SIZE, |bank| bank.len()));
+        for bank in self.ram.iter_mut() {
+            match chunks.next() {
+                Some(chunk) => bank[..chunk.len()].copy_from_slice(chunk),
+                None => break,
+            }
+        }
+
+        let ram_len = self.ram.iter().map(|bank| bank.len()).sum::<usize>();
+        if data.len() > ram_len {
+            self.set_rtc_data(&data[ram_len..]);
+        }
+    }
+
+    /// Check if the rumble motor of the cartridge is on
+    pub fn get_rumble(&self) -> bool {
+        match &self.cartridge {
+            CartridgeState::MBC5(state) => state.rumble,
+            _ => false,
+        }
+    }
+
+    /// Get the real time clock state in save file format, if the cartridge has one
+    pub fn get_rtc_data(&self) -> Option<Vec<Byte>> {
+        match &self.cartridge {
+            CartridgeState::MBC3(MBC3State { rtc: Some(rtc), .. }) => Some(rtc.to_bytes()),
+            _ => None,
+        }
+    }
+
+    /// Restore the real time clock state from save file format
+    pub fn set_rtc_data(&mut self, data: &[Byte]) {
+        if let CartridgeState::MBC3(MBC3State { rtc: Some(rtc), .. }) = &mut self.cartridge {
+            if let Some(new_rtc) = RealTimeClock::from_bytes(data) {
+                *rtc = new_rtc;
             }
         }
     }
@@ -149,42 +894,170 @@ impl Memory {
             CartridgeState::None => CartridgeType::None,
             CartridgeState::RomOnly(_) => CartridgeType::RomOnly,
             CartridgeState::MBC1(_) => CartridgeType::MBC1,
+            CartridgeState::MBC2(_) => CartridgeType::MBC2,
+            CartridgeState::MBC3(_) => CartridgeType::MBC3,
+            CartridgeState::MBC5(_) => CartridgeType::MBC5,
         }
     }
 
-    /// Get cartridge type given rom (in vec)
-    pub fn get_cartridge_type_rom(&self, rom: &[Byte]) -> CartridgeType {
-        let rom_type = rom[MBC_TYPE_ADDRESS as usize];
-        match rom_type {
-            0x00 => CartridgeType::RomOnly,
-            0x01 => CartridgeType::MBC1,
-            _ => unimplemented!("Rom type {:#04X?}", rom_type),
+    /// MBC1 multicarts are 8 Mbit roms with a second nintendo logo at bank 0x10
+    fn is_multicart(&self) -> bool {
+        let logo = NINTENDO_LOGO_ADDRESS as usize..(NINTENDO_LOGO_ADDRESS as usize + 0x30);
+        self.rom.len() == 64 && self.rom[0][logo.clone()] == self.rom[0x10][logo]
+    }
+
+    /// Set the I/O registers to the state the boot rom of the model leaves behind, and unmap the boot rom.
+    /// The cartridge must be loaded first, the cpu and timer state after boot depend on its header
+    pub fn skip_boot(&mut self, model: Model) {
+        debug_assert!(
+            self.header.is_some(),
+            "skip_boot called before a cartridge was loaded"
+        );
+        for (address, byte) in POST_BOOT_IO {
+            self.write_io(*address, *byte);
+        }
+        match model {
+            Model::SGB | Model::SGB2 => {
+                // both joypad lines deselected after the SNES handshake, and no sound
+                self.write_io(JOYPAD_REGISTER_ADDRESS, 0xFF);
+                self.write_io(SOUND_CONTROL_ADDRESS, 0xF0);
+            }
+            Model::CGB => self.write_io(DMA_ADDRESS, 0x00),
+            Model::DMG0 | Model::DMG | Model::MGB => (),
         }
+        self.boot_mapped = false;
     }
 
-    /// Get rom size
-    pub fn get_rom_size_rom(&self, rom: &[Byte]) -> usize {
-        let rom_size = rom[ROM_SIZE_ADDRESS as usize].into();
-        rom_size
+    fn unload_boot(&mut self, _byte: Byte) {
+        info!("Unloading boot rom");
+        self.boot_mapped = false;
     }
 
-    /// Get ram size
-    pub fn get_ram_size_rom(&self, rom: &[Byte]) -> usize {
-        let ram_size = rom[RAM_SIZE_ADDRESS as usize].into();
-        ram_size
+    /// Schedule an OAM DMA transfer, a transfer already running continues until the new one starts
+    fn start_dma(&mut self, byte: Byte) {
+        self.dma_start = Some(DmaStart {
+            source: bytes2word(0x00, byte),
+            delay: DMA_DELAY,
+        });
     }
 
-    fn unload_boot(&mut self) {
-        info!("Unloading boot rom");
-        self.memory[..BOOTROM_SIZE].copy_from_slice(&self.rom[0][..BOOTROM_SIZE]);
+    /// Advance the OAM DMA transfer, copying one byte per m-cycle
+    pub fn tick_dma(&mut self, mcycles: u8) {
+        // a register write during the previous m-cycle starts the delay after this one
+        let started = self.take_io_written(DMA_ADDRESS);
+
+        for _ in 0..mcycles {
+            if let Some(mut transfer) = self.dma {
+                let value = self.dma_read(transfer.source + transfer.index);
+                self.memory[(OAM_ADDRESS + transfer.index) as usize] = value;
+                transfer.index += 1;
+                self.dma = (transfer.index < DMA_SIZE).then_some(transfer);
+            }
+
+            if started {
+                continue;
+            }
+            if let Some(mut start) = self.dma_start {
+                start.delay -= 1;
+                if start.delay == 0 {
+                    self.dma = Some(DmaTransfer {
+                        source: start.source,
+                        index: 0,
+                    });
+                    self.dma_start = None;
+                } else {
+                    self.dma_start = Some(start);
+                }
+            }
+        }
+    }
+
+    /// OAM DMA owns OAM and the bus it reads from, which is the video bus for VRAM sources
+    /// and the external bus for everything else. I/O registers and HRAM stay reachable
+    fn dma_blocked(&self, address: Address) -> bool {
+        let Some(transfer) = self.dma else {
+            return false;
+        };
+        let video_source = (VRAM_ADDRESS..=0x9FFF).contains(&transfer.source);
+        match address {
+            OAM_ADDRESS..=UNUSABLE_END_ADDRESS => true,
+            IO_ADDRESS..=0xFFFF => false,
+            VRAM_ADDRESS..=0x9FFF => video_source,
+            _ => !video_source,
+        }
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    /// Write the enabled GameShark codes, the PPU does this at the start of VBlank like the real device.
+    /// The writes bypass the cpu bus restrictions, and codes outside of ram are ignored
+    pub fn apply_cheats(&mut self) {
+        for (bank, address, data) in self.cheats.ram_writes() {
+            match (bank, address) {
+                (Some(bank), RAM_ADDRESS..=0xBFFF) if !self.ram.is_empty() => {
+                    let bank = bank % self.ram.len();
+                    let offset = (address - RAM_ADDRESS) as usize;
+                    if let Some(byte) = self.ram[bank].get_mut(offset) {
+                        *byte = data;
+                        self.ram_dirty = true;
+                    }
+                }
+                (_, RAM_ADDRESS..=0xBFFF) => self.write_cartridge(address, data),
+                (_, ECHO_ADDRESS..=ECHO_END_ADDRESS) => {
+                    self.memory[(address - ECHO_OFFSET) as usize] = data;
+                }
+                (_, VRAM_ADDRESS..=0x9FFF)
+                | (_, WORK_RAM_ADDRESS..=0xDFFF)
+                | (_, OAM_ADDRESS..=OAM_END_ADDRESS)
+                | (_, HRAM_ADDRESS..=0xFFFE) => self.memory[address as usize] = data,
+                // rom, the unusable area, I/O registers and IE
+                _ => (),
+            }
+        }
+    }
+
+    /// Enable or disable the PPU mode access restrictions, disabling is only meant for debugging
+    pub fn set_ppu_restrictions(&mut self, enabled: bool) {
+        self.ppu_restrictions = enabled;
+    }
+
+    /// Make the cpu read LY as 0x90 without touching the register, only meant for traces
+    pub fn set_ly_stub(&mut self, enabled: bool) {
+        self.ly_stub = enabled;
+    }
+
+    /// VRAM is blocked during pixel transfer (mode 3), and OAM during OAM scan (mode 2) and mode 3
+    fn ppu_blocked(&self, address: Address) -> bool {
+        if !self.ppu_restrictions || self.memory[LCDC_ADDRESS as usize] & LCDC_ENABLE_FLAG == 0 {
+            return false;
+        }
+        let mode = self.memory[LCD_STATUS_ADDRESS as usize] & PPU_MODE_MASK;
+        match address {
+            VRAM_ADDRESS..=0x9FFF => mode == 3,
+            OAM_ADDRESS..=UNUSABLE_END_ADDRESS => mode == 2 || mode == 3,
+            _ => false,
+        }
     }
 
-    fn dma(&mut self, byte: Byte) {
-        let size = 0x100;
-        let src = bytes2word(0x00, byte) as usize;
+    /// Read the DMA source, sources from 0xE000 upward read work ram
+    fn dma_read(&self, address: Address) -> Byte {
+        match address {
+            0x0000..=0x7FFF | RAM_ADDRESS..=0xBFFF => self.read_cartridge(address),
+            ECHO_ADDRESS..=0xFFFF => self.memory[(address - ECHO_OFFSET) as usize],
+            _ => self.memory[address as usize],
+        }
+    }
 
-        self.memory
-            .copy_within(src..(src + size), OAM_ADDRESS as usize);
+    /// Read VRAM or OAM as the PPU, bypassing the cpu bus
+    pub fn read_video(&self, address: Address) -> Byte {
+        assert!(matches!(address, VRAM_ADDRESS..=0x9FFF | OAM_ADDRESS..=OAM_END_ADDRESS));
+        self.memory[address as usize]
     }
 
     /// Wrapping add value to address
@@ -199,3 +1072,18 @@ impl Memory {
         self.memory[..rom.len()].copy_from_slice(&rom);
     }
 }
+
+impl Bus for Memory {
+    fn read_byte(&self, address: Address) -> Byte {
+        Memory::read_byte(self, address)
+    }
+
+    fn write_byte(&mut self, address: Address, byte: Byte) {
+        Memory::write_byte(self, address, byte)
+    }
+
+    /// Run the timer, PPU, DMA and cartridge clock
+    fn tick(&mut self, clock: &mut Clock, mcycles: u8) {
+        clock.tick(mcycles, self);
+    }
+}
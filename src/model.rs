@@ -0,0 +1,58 @@
+use std::{fmt, str::FromStr};
+
+/// Game Boy hardware model, which decides the state the boot rom leaves behind
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Model {
+    /// Original Game Boy with the early boot rom
+    DMG0,
+    /// Original Game Boy (revisions A, B and C)
+    #[default]
+    DMG,
+    /// Game Boy Pocket and Light
+    MGB,
+    /// Super Game Boy
+    SGB,
+    /// Super Game Boy 2
+    SGB2,
+    /// Game Boy Color, emulated in monochrome mode
+    CGB,
+}
+
+impl Model {
+    pub const NAMES: [&'static str; 6] = ["dmg0", "dmg", "mgb", "sgb", "sgb2", "cgb"];
+
+    /// Check if the model is a Super Game Boy
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Self::SGB | Self::SGB2)
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Self::DMG0),
+            "dmg" => Ok(Self::DMG),
+            "mgb" => Ok(Self::MGB),
+            "sgb" => Ok(Self::SGB),
+            "sgb2" => Ok(Self::SGB2),
+            "cgb" => Ok(Self::CGB),
+            _ => Err(format!("Unknown model {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::DMG0 => "DMG0",
+            Self::DMG => "DMG",
+            Self::MGB => "MGB",
+            Self::SGB => "SGB",
+            Self::SGB2 => "SGB2",
+            Self::CGB => "CGB",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,368 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::utils::Byte;
+
+const IPS_MAGIC: &[Byte] = b"PATCH";
+const IPS_EOF: &[Byte] = b"EOF";
+const UPS_MAGIC: &[Byte] = b"UPS1";
+const BPS_MAGIC: &[Byte] = b"BPS1";
+/// Source, target and patch crc32 at the end of UPS and BPS patches
+const FOOTER_SIZE: usize = 12;
+
+/// Errors from applying a rom patch
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// The patch does not start with a known magic number
+    UnknownFormat,
+    /// The patch ends in the middle of a record
+    Truncated,
+    /// A record reads or writes outside of the rom
+    OutOfBounds,
+    /// The rom is not the one the patch was made for
+    SourceChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    TargetChecksum {
+        expected: u32,
+        actual: u32,
+    },
+    /// The patch file itself is corrupted
+    PatchChecksum {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFormat => write!(f, "Unknown patch format"),
+            Self::Truncated => write!(f, "Patch is truncated"),
+            Self::OutOfBounds => write!(f, "Patch accesses data outside of the ROM"),
+            Self::SourceChecksum { expected, actual } => write!(
+                f,
+                "ROM checksum mismatch, patch expects {:#010X} but ROM has {:#010X}",
+                expected, actual
+            ),
+            Self::TargetChecksum { expected, actual } => write!(
+                f,
+                "Patched ROM checksum mismatch, patch expects {:#010X} but got {:#010X}",
+                expected, actual
+            ),
+            Self::PatchChecksum { expected, actual } => write!(
+                f,
+                "Patch checksum mismatch, patch has {:#010X} but computed {:#010X}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PatchFormat {
+    IPS,
+    UPS,
+    BPS,
+}
+
+impl PatchFormat {
+    pub const EXTENSIONS: [&'static str; 3] = ["ips", "ups", "bps"];
+
+    /// Detect the format from the magic number at the start of the patch
+    pub fn detect(patch: &[Byte]) -> Option<Self> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(Self::IPS)
+        } else if patch.starts_with(UPS_MAGIC) {
+            Some(Self::UPS)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(Self::BPS)
+        } else {
+            None
+        }
+    }
+}
+
+/// Find a patch file next to the rom with the same name, e.g. game.ips for game.gb
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    PatchFormat::EXTENSIONS
+        .iter()
+        .map(|extension| rom_path.with_extension(extension))
+        .find(|path| path.is_file())
+}
+
+/// Apply the patch to the rom, detecting the format from its magic number
+pub fn apply_patch(rom: &[Byte], patch: &[Byte]) -> Result<Vec<Byte>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::IPS) => apply_ips(rom, patch),
+        Some(PatchFormat::UPS) => apply_ups(rom, patch),
+        Some(PatchFormat::BPS) => apply_bps(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+/// Apply an IPS patch, which is a list of (offset, data) records and has no checksums
+pub fn apply_ips(rom: &[Byte], patch: &[Byte]) -> Result<Vec<Byte>, PatchError> {
+    let mut reader = PatchReader::new(patch);
+    if reader.bytes(IPS_MAGIC.len())? != IPS_MAGIC {
+        return Err(PatchError::UnknownFormat);
+    }
+
+    let mut target = rom.to_vec();
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = big_endian(offset);
+        let size = big_endian(reader.bytes(2)?);
+
+        let data = if size == 0 {
+            // run length encoded record
+            let length = big_endian(reader.bytes(2)?);
+            vec![reader.byte()?; length]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // optional truncation extension
+    if let Ok(size) = reader.bytes(3) {
+        target.truncate(big_endian(size));
+    }
+    Ok(target)
+}
+
+/// Apply an UPS patch, which xors the rom with the patch data
+pub fn apply_ups(rom: &[Byte], patch: &[Byte]) -> Result<Vec<Byte>, PatchError> {
+    let (mut reader, checksums) = PatchReader::with_footer(patch, UPS_MAGIC)?;
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    checksums.verify_source(rom, source_size)?;
+
+    let mut target = rom.to_vec();
+    target.resize(target_size, 0);
+    let mut position: usize = 0;
+    while reader.remaining() > 0 {
+        position = position
+            .checked_add(reader.varint()?)
+            .ok_or(PatchError::OutOfBounds)?;
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                position += 1;
+                break;
+            }
+            if position < target.len() {
+                target[position] ^= byte;
+            }
+            position += 1;
+        }
+    }
+
+    checksums.verify_target(&target)?;
+    Ok(target)
+}
+
+/// Apply a BPS patch, which builds the target from source, target and patch copies
+pub fn apply_bps(rom: &[Byte], patch: &[Byte]) -> Result<Vec<Byte>, PatchError> {
+    let (mut reader, checksums) = PatchReader::with_footer(patch, BPS_MAGIC)?;
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    checksums.verify_source(rom, source_size)?;
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+    while reader.remaining() > 0 {
+        let data = reader.varint()?;
+        let length = (data >> 2) + 1;
+        if length > target_size - target.len() {
+            return Err(PatchError::OutOfBounds);
+        }
+        match data & 0b11 {
+            // source read
+            0 => {
+                let bytes = rom
+                    .get(target.len()..target.len() + length)
+                    .ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(bytes);
+            }
+            // target read
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // source copy
+            2 => {
+                source_offset = reader.relative_offset(source_offset)?;
+                let bytes = rom
+                    .get(source_offset..source_offset.saturating_add(length))
+                    .ok_or(PatchError::OutOfBounds)?;
+                target.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // target copy, byte by byte since the copy can overlap its own output
+            _ => {
+                target_offset = reader.relative_offset(target_offset)?;
+                for _ in 0..length {
+                    let byte = *target.get(target_offset).ok_or(PatchError::OutOfBounds)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(PatchError::OutOfBounds);
+    }
+    checksums.verify_target(&target)?;
+    Ok(target)
+}
+
+/// CRC-32 (IEEE) as used by the UPS and BPS footers
+pub fn crc32(data: &[Byte]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn big_endian(bytes: &[Byte]) -> usize {
+    bytes.iter().fold(0, |value, b| (value << 8) | *b as usize)
+}
+
+/// Checksums from the UPS and BPS footer
+struct Checksums {
+    source: u32,
+    target: u32,
+}
+
+impl Checksums {
+    fn verify_source(&self, rom: &[Byte], source_size: usize) -> Result<(), PatchError> {
+        let actual = crc32(rom);
+        if actual != self.source || rom.len() != source_size {
+            return Err(PatchError::SourceChecksum {
+                expected: self.source,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    fn verify_target(&self, target: &[Byte]) -> Result<(), PatchError> {
+        let actual = crc32(target);
+        if actual != self.target {
+            return Err(PatchError::TargetChecksum {
+                expected: self.target,
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Bounds checked cursor over the patch data
+struct PatchReader<'a> {
+    data: &'a [Byte],
+    position: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [Byte]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Reader over a UPS or BPS patch body, after checking the magic number and patch checksum
+    fn with_footer(patch: &'a [Byte], magic: &[Byte]) -> Result<(Self, Checksums), PatchError> {
+        if !patch.starts_with(magic) {
+            return Err(PatchError::UnknownFormat);
+        }
+        if patch.len() < magic.len() + FOOTER_SIZE {
+            return Err(PatchError::Truncated);
+        }
+        let (body, footer) = patch.split_at(patch.len() - FOOTER_SIZE);
+        let crc = |i: usize| u32::from_le_bytes(footer[i * 4..i * 4 + 4].try_into().unwrap());
+
+        let actual = crc32(&patch[..patch.len() - 4]);
+        if actual != crc(2) {
+            return Err(PatchError::PatchChecksum {
+                expected: crc(2),
+                actual,
+            });
+        }
+
+        let reader = Self {
+            data: body,
+            position: magic.len(),
+        };
+        let checksums = Checksums {
+            source: crc(0),
+            target: crc(1),
+        };
+        Ok((reader, checksums))
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn byte(&mut self) -> Result<Byte, PatchError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [Byte], PatchError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position.saturating_add(count))
+            .ok_or(PatchError::Truncated)?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Variable length number, 7 bits per byte with the top bit marking the last byte
+    fn varint(&mut self) -> Result<usize, PatchError> {
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|data| value.checked_add(data))
+                .ok_or(PatchError::OutOfBounds)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfBounds)?;
+            value = value.checked_add(shift).ok_or(PatchError::OutOfBounds)?;
+        }
+    }
+
+    /// BPS copy offset, a varint with the sign in the lowest bit
+    fn relative_offset(&mut self, offset: usize) -> Result<usize, PatchError> {
+        let data = self.varint()?;
+        let delta = data >> 1;
+        if data & 1 != 0 {
+            offset.checked_sub(delta).ok_or(PatchError::OutOfBounds)
+        } else {
+            offset.checked_add(delta).ok_or(PatchError::OutOfBounds)
+        }
+    }
+}
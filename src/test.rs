@@ -1,17 +1,30 @@
 #[cfg(test)]
 mod tests {
     use sdl2::keyboard::Keycode;
+    use serde_json::Value;
 
+    use crate::asm::{assemble, assemble_at, AsmError};
+    use crate::boot::BOOT_ROM;
+    use crate::cheat::{Cheat, CheatError};
     use crate::clock::Clock;
     use crate::cpu::{
-        Condition, Instruction, Register, Register16, SizedInstruction, CARRY_FLAG, CPU,
-        HALF_CARRY_FLAG, SUBTRACT_FLAG, ZERO_FLAG,
+        Condition, Instruction, Register, Register16, SizedInstruction, CARRY_FLAG, CB_PREFIX, CPU,
+        HALF_CARRY_FLAG, INTERRUPT_ENABLE_ADDRESS, INTERRUPT_FLAG_ADDRESS, SERIAL_FLAG,
+        SUBTRACT_FLAG, TIMER_FLAG, VBLANK_FLAG, ZERO_FLAG,
     };
+    use crate::disasm::{
+        disassemble, disassemble_bank, disassemble_instruction, format_instruction, parse_symbols,
+        RomDisassembly, Symbols,
+    };
+    use crate::header::{CartridgeError, CartridgeHeader, CgbSupport, Destination};
     use crate::joypad::{
         Joypad, A_BUTTON, BUTTONS_FLAG, B_BUTTON, DOWN_BUTTON, DPAD_FLAG, JOYPAD_REGISTER_ADDRESS,
         LEFT_BUTTON, RIGHT_BUTTON, SELECT_BUTTON, START_BUTTON, UP_BUTTON,
     };
-    use crate::memory::Memory;
+    use crate::memory::{Bus, Memory};
+    use crate::model::Model;
+    use crate::patch::{apply_patch, crc32, PatchError, PatchFormat};
+    use crate::utils::{Address, Byte, Word};
 
     #[test]
     fn memory() {
@@ -23,6 +36,1031 @@ mod tests {
         assert_eq!(memory.read_byte(address), byte);
     }
 
+    #[test]
+    fn echo_ram() {
+        let mut memory = Memory::new();
+
+        // work ram writes show up in echo ram
+        memory.write_byte(0xC000, 0x12);
+        memory.write_byte(0xDDFF, 0x34);
+        assert_eq!(memory.read_byte(0xE000), 0x12);
+        assert_eq!(memory.read_byte(0xFDFF), 0x34);
+
+        // echo ram writes show up in work ram
+        memory.write_byte(0xE123, 0x56);
+        memory.write_byte(0xFDFE, 0x78);
+        assert_eq!(memory.read_byte(0xC123), 0x56);
+        assert_eq!(memory.read_byte(0xDDFE), 0x78);
+
+        // the end of work ram is not mirrored
+        memory.write_byte(0xDE00, 0x9A);
+        assert_eq!(memory.read_byte(0xFE00), 0x00);
+    }
+
+    #[test]
+    fn unusable_area() {
+        let mut memory = Memory::new();
+
+        memory.write_byte(0xFEA0, 0x12);
+        memory.write_byte(0xFEFF, 0x34);
+        assert_eq!(memory.read_byte(0xFEA0), 0x00);
+        assert_eq!(memory.read_byte(0xFEFF), 0x00);
+
+        // reads 0xFF while OAM is blocked
+        memory.write_byte(0xFF40, 0x80);
+        memory.write_io(0xFF41, 0x02);
+        assert_eq!(memory.read_byte(0xFEA0), 0xFF);
+    }
+
+    #[test]
+    fn io_register_masks() {
+        let mut memory = Memory::new();
+
+        // unmapped registers read as 0xFF and ignore writes
+        memory.write_byte(0xFF03, 0x12);
+        assert_eq!(memory.read_byte(0xFF03), 0xFF);
+
+        // unused bits read as 1
+        memory.write_byte(Clock::TAC_ADDRESS, 0x00);
+        assert_eq!(memory.read_byte(Clock::TAC_ADDRESS), 0xF8);
+        memory.write_byte(0xFF02, 0x00);
+        assert_eq!(memory.read_byte(0xFF02), 0x7E);
+
+        // STAT mode and coincidence bits are read only
+        memory.write_io(0xFF41, 0x03);
+        memory.write_byte(0xFF41, 0x7C);
+        assert_eq!(memory.read_byte(0xFF41), 0xFB);
+
+        // LY is read only
+        memory.write_io(0xFF44, 0x10);
+        memory.write_byte(0xFF44, 0x20);
+        assert_eq!(memory.read_byte(0xFF44), 0x10);
+    }
+
+    #[test]
+    fn oam_dma() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        for i in 0..0xA0 {
+            memory.write_byte(0xC000 + i, i as u8);
+        }
+        memory.write_byte(0xFF80, 0x12);
+        memory.write_byte(0x8000, 0x34);
+
+        // sources from 0xE000 read work ram
+        memory.write_byte(0xFF46, 0xE0);
+        clock.tick(3, &mut memory);
+        assert_eq!(memory.read_byte(0xC000), 0x00);
+
+        // the external bus and OAM are blocked during the transfer, the video bus is not
+        clock.tick(2, &mut memory);
+        assert_eq!(memory.read_byte(0xC000), 0xFF);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+        assert_eq!(memory.read_byte(0x8000), 0x34);
+        assert_eq!(memory.read_byte(0xFF80), 0x12);
+        assert_eq!(memory.read_byte(0xFF46), 0xE0);
+        memory.write_byte(0xC000, 0x55);
+
+        clock.tick(159, &mut memory);
+        assert_eq!(memory.read_byte(0xFE00), 0x00);
+        assert_eq!(memory.read_byte(0xFE9F), 0x9F);
+        assert_eq!(memory.read_byte(0xC000), 0x00);
+    }
+
+    #[test]
+    fn ppu_mode_restrictions() {
+        let mut memory = Memory::new();
+        memory.write_byte(0x8000, 0x12);
+        memory.write_byte(0xFE00, 0x34);
+        memory.write_byte(0xFF40, 0x80);
+
+        // mode 2 blocks OAM only
+        memory.write_io(0xFF41, 0x02);
+        assert_eq!(memory.read_byte(0x8000), 0x12);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+        memory.write_byte(0xFE00, 0x56);
+
+        // mode 3 blocks VRAM and OAM
+        memory.write_io(0xFF41, 0x03);
+        assert_eq!(memory.read_byte(0x8000), 0xFF);
+        assert_eq!(memory.read_byte(0xFE00), 0xFF);
+        memory.write_byte(0x8000, 0x78);
+
+        memory.set_ppu_restrictions(false);
+        assert_eq!(memory.read_byte(0x8000), 0x12);
+        assert_eq!(memory.read_byte(0xFE00), 0x34);
+
+        // no restrictions while the lcd is off
+        memory.set_ppu_restrictions(true);
+        memory.write_byte(0xFF40, 0x00);
+        assert_eq!(memory.read_byte(0x8000), 0x12);
+    }
+
+    #[test]
+    fn ppu_timing() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        let mut cpu = CPU::new();
+        memory.write_byte(0xFF40, 0x80);
+
+        // ldh a, [$41] reads STAT in its third m-cycle, where the PPU enters mode 3
+        memory.write_test(vec![0xF0, 0x41]);
+        clock.tick(18, &mut memory);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, 2);
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(cpu.a & 0b11, 3);
+
+        // the clock steps the PPU into vblank without graphics
+        for _ in 0..114 * 144 - 22 {
+            clock.tick(1, &mut memory);
+        }
+        assert_eq!(memory.read_byte(0xFF44), 143);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG_ADDRESS) & VBLANK_FLAG, 0);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(0xFF44), 144);
+        assert_eq!(memory.read_byte(0xFF41) & 0b11, 1);
+        assert_ne!(memory.read_byte(INTERRUPT_FLAG_ADDRESS) & VBLANK_FLAG, 0);
+        assert!(clock.take_frame().is_some());
+        assert!(clock.take_frame().is_none());
+    }
+
+    #[test]
+    fn skip_boot_state() {
+        // same order as the cli: load the rom, then skip the boot rom
+        let mut rom = banked_rom(0x00, 0x00, 0x00);
+        rom[0x134] = 0xE7;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+        let mut memory = Memory::new();
+        memory.load_cartidge(rom).unwrap();
+        memory.skip_boot(Model::DMG);
+        let mut clock = Clock::new_skip_boot(Model::DMG, &memory);
+        let cpu = CPU::new_skip_boot(Model::DMG, &memory);
+
+        assert_eq!(cpu.pc, 0x100);
+        // a header checksum of 0 leaves only the zero flag set
+        assert_eq!(cpu.f, 0x80);
+        assert_eq!(memory.read_byte(0xFF40), 0x91);
+        assert_eq!(memory.read_byte(0xFF47), 0xFC);
+        assert_eq!(memory.read_byte(0xFF26), 0xF1);
+        assert_eq!(memory.read_byte(Clock::TAC_ADDRESS), 0xF8);
+        assert_eq!(memory.read_byte(0xFF0F), 0xE1);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0xAB);
+    }
+
+    #[test]
+    #[should_panic(expected = "before a cartridge was loaded")]
+    fn skip_boot_without_cartridge() {
+        let mut memory = Memory::new();
+        memory.skip_boot(Model::DMG);
+    }
+
+    #[test]
+    fn doctor_state() {
+        let mut memory = Memory::new();
+        let mut rom = vec![0x00; 0x104];
+        rom[0x101..].copy_from_slice(&[0xC3, 0x13, 0x02]);
+        memory.write_test(rom);
+        let mut cpu = CPU::new_skip_boot(Model::DMG, &memory);
+        cpu.f = 0xB0;
+
+        assert_eq!(
+            cpu.doctor_state(&memory),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+        );
+        cpu.pc = 0xFFFE;
+        assert!(cpu
+            .doctor_state(&memory)
+            .ends_with("PC:FFFE PCMEM:00,00,00,00"));
+    }
+
+    #[test]
+    fn ly_stub() {
+        let mut memory = Memory::new();
+        memory.write_io(0xFF44, 0x12);
+        memory.set_ly_stub(true);
+        assert_eq!(memory.read_byte(0xFF44), 0x90);
+        assert_eq!(memory.read_io(0xFF44), 0x12);
+        memory.set_ly_stub(false);
+        assert_eq!(memory.read_byte(0xFF44), 0x12);
+    }
+
+    #[test]
+    fn model_boot_state() {
+        assert_eq!("SGB2".parse(), Ok(Model::SGB2));
+        assert!("gba".parse::<Model>().is_err());
+
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x00, 0x00, 0x00)).unwrap();
+        let cpu = CPU::new_skip_boot(Model::MGB, &memory);
+        assert_eq!((cpu.a, cpu.f), (0xFF, 0xB0));
+        let cpu = CPU::new_skip_boot(Model::DMG0, &memory);
+        assert_eq!((cpu.a, cpu.f, cpu.b), (0x01, 0x00, 0xFF));
+
+        memory.skip_boot(Model::SGB);
+        let cpu = CPU::new_skip_boot(Model::SGB, &memory);
+        assert_eq!((cpu.a, cpu.c, cpu.h, cpu.l), (0x01, 0x14, 0xC0, 0x60));
+        assert_eq!(memory.read_byte(JOYPAD_REGISTER_ADDRESS), 0xFF);
+        assert_eq!(memory.read_byte(0xFF26), 0xF0);
+    }
+
+    #[test]
+    fn builtin_boot_rom() {
+        let mut rom = banked_rom(0x00, 0x00, 0x00);
+        rom[0x104] = 0xCE;
+        rom[0x105] = 0xED;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+
+        let mut memory = Memory::new();
+        let mut cpu = CPU::new();
+        let mut clock = Clock::new();
+        memory.load_boot(BOOT_ROM.to_vec());
+        memory.load_cartidge(rom).unwrap();
+
+        while cpu.pc != 0x100 {
+            cpu.execute(&mut memory, &mut clock);
+        }
+
+        assert_eq!((cpu.a, cpu.f, cpu.b, cpu.c), (0x01, 0xB0, 0x00, 0x13));
+        assert_eq!((cpu.d, cpu.e, cpu.h, cpu.l), (0x00, 0xD8, 0x01, 0x4D));
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(memory.read_byte(0xFF40), 0x91);
+        assert_eq!(memory.read_byte(0xFF42), 0x00);
+        // 0xC of the logo doubled to two rows of 0xF0
+        assert_eq!(memory.read_byte(0x8010), 0xF0);
+        assert_eq!(memory.read_byte(0x8012), 0xF0);
+        // 0xE of the logo
+        assert_eq!(memory.read_byte(0x8014), 0xFC);
+        assert_eq!(memory.read_byte(0x9904), 0x01);
+        assert_eq!(memory.read_byte(0x9910), 0x19);
+        assert_eq!(memory.read_byte(0x992F), 0x18);
+        // the boot rom is unmapped
+        assert_eq!(memory.read_byte(0x0000), 0x00);
+    }
+
+    #[test]
+    fn io_div_write() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        clock.tick(100, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 1);
+
+        // any write resets the divider
+        memory.write_byte(Clock::DIV_ADDRESS, 0x55);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0);
+        clock.tick(1, &mut memory);
+        clock.tick(60, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0);
+        clock.tick(4, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 1);
+    }
+
+    #[test]
+    fn tima_overflow_reload() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        // increment every 4 m-cycles
+        memory.write_byte(Clock::TAC_ADDRESS, 0x05);
+        memory.write_byte(Clock::TMA_ADDRESS, 0x42);
+        memory.write_byte(Clock::TIMA_ADDRESS, 0xFF);
+
+        // TIMA reads 0x00 for one m-cycle before TMA is loaded
+        clock.tick(4, &mut memory);
+        assert_eq!(memory.read_byte(Clock::TIMA_ADDRESS), 0x00);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG_ADDRESS) & TIMER_FLAG, 0);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(Clock::TIMA_ADDRESS), 0x42);
+        assert_eq!(
+            memory.read_byte(INTERRUPT_FLAG_ADDRESS) & TIMER_FLAG,
+            TIMER_FLAG
+        );
+
+        // writing TIMA in the overflow cycle cancels the reload
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, 0);
+        memory.write_byte(Clock::TIMA_ADDRESS, 0xFF);
+        clock.tick(4, &mut memory);
+        memory.write_byte(Clock::TIMA_ADDRESS, 0x10);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(Clock::TIMA_ADDRESS), 0x10);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG_ADDRESS) & TIMER_FLAG, 0);
+    }
+
+    #[test]
+    fn cpu_access_timing() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        let mut cpu = CPU::new();
+        cpu.pc = 0xC000;
+        cpu.h = 0xFF;
+        cpu.l = 0x04;
+        // ld a, (hl); ld (hl), a
+        memory.write_byte(0xC000, 0x7E);
+        memory.write_byte(0xC001, 0x77);
+
+        // DIV increments after 2 more m-cycles, on the read cycle of the instruction
+        clock.tick(62, &mut memory);
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(cpu.a, 0x01);
+        assert_eq!(clock.get_timestamp(), 64);
+
+        // the write resets DIV on the second m-cycle, before the next tick
+        cpu.execute(&mut memory, &mut clock);
+        clock.tick(63, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0x00);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0x01);
+    }
+
+    /// Build a rom with the given header, where every bank is filled with its bank number
+    fn banked_rom(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let bank_count = 2 << rom_size;
+        let mut rom = Vec::with_capacity(bank_count * 0x4000);
+        for bank in 0..bank_count {
+            rom.extend([bank as u8; 0x4000]);
+        }
+        rom[0x147] = cartridge_type;
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+        rom
+    }
+
+    #[test]
+    fn header_parse() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x13F].copy_from_slice(b"POKEMON_SLV");
+        rom[0x13F..0x143].copy_from_slice(b"AAXE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x146] = 0x03;
+        rom[0x147] = 0x10;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14C] = 0x02;
+        rom[0x14D] = CartridgeHeader::compute_header_checksum(&rom);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code, Some(String::from("AAXE")));
+        assert_eq!(header.cgb_support, CgbSupport::Compatible);
+        assert!(header.sgb_support);
+        assert_eq!(header.licensee(), "01");
+        assert_eq!(header.destination, Destination::Overseas);
+        assert_eq!(header.version, 2);
+        assert_eq!(header.rom_banks(), Ok(2));
+        assert_eq!(header.ram_banks(), Ok(4));
+        assert!(header.has_battery() && header.has_rtc());
+    }
+
+    #[test]
+    fn header_errors() {
+        let mut memory = Memory::new();
+        assert_eq!(
+            memory.load_cartidge(vec![0; 0x100]),
+            Err(CartridgeError::Truncated {
+                expected: 0x150,
+                actual: 0x100
+            })
+        );
+
+        let mut rom = banked_rom(0x00, 0x00, 0x00);
+        rom[0x14D] ^= 0xFF;
+        assert!(matches!(
+            memory.load_cartidge(rom),
+            Err(CartridgeError::HeaderChecksum { .. })
+        ));
+
+        let mut rom = banked_rom(0x01, 0x02, 0x00);
+        rom.truncate(0x8000);
+        assert_eq!(
+            memory.load_cartidge(rom),
+            Err(CartridgeError::Truncated {
+                expected: 0x20000,
+                actual: 0x8000
+            })
+        );
+
+        assert_eq!(
+            memory.load_cartidge(banked_rom(0xFC, 0x00, 0x00)),
+            Err(CartridgeError::UnsupportedType(0xFC))
+        );
+    }
+
+    #[test]
+    fn mbc1_rom_bank() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x01, 0x06, 0x00)).unwrap();
+
+        assert_eq!(memory.read_byte(0x4000), 1);
+        memory.write_byte(0x2000, 0x05);
+        assert_eq!(memory.read_byte(0x4000), 5);
+        // bank 0 maps to bank 1
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 1);
+        // upper bits from bank2
+        memory.write_byte(0x2000, 0x02);
+        memory.write_byte(0x4000, 0x01);
+        assert_eq!(memory.read_byte(0x7FFF), 0x22);
+        assert_eq!(memory.read_byte(0x0000), 0);
+        // advanced mode maps bank2 to 0x0000-0x3FFF
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0x0000), 0x20);
+    }
+
+    #[test]
+    fn mbc1_ram_bank() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x03, 0x01, 0x03)).unwrap();
+
+        // disabled ram is open bus
+        memory.write_byte(0xA000, 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x02);
+        assert_eq!(memory.read_byte(0xA000), 0x00);
+        memory.write_byte(0xA000, 0x34);
+        memory.write_byte(0x4000, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+        memory.write_byte(0x4000, 0x02);
+        assert_eq!(memory.read_byte(0xA000), 0x34);
+    }
+
+    #[test]
+    fn mbc2_registers() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x06, 0x03, 0x00)).unwrap();
+
+        // address bit 8 selects the rom bank register
+        memory.write_byte(0x0100, 0x03);
+        assert_eq!(memory.read_byte(0x4000), 3);
+        memory.write_byte(0x0000, 0x0A);
+        assert_eq!(memory.read_byte(0x4000), 3);
+        memory.write_byte(0x2100, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn mbc2_ram() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x06, 0x01, 0x00)).unwrap();
+
+        assert_eq!(memory.read_byte(0xA000), 0xFF);
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0xA000, 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0xF2);
+        // ram echoes every 512 bytes
+        assert_eq!(memory.read_byte(0xA200), 0xF2);
+        assert_eq!(memory.read_byte(0xBE00), 0xF2);
+        memory.write_byte(0xB1FF, 0x0C);
+        assert_eq!(memory.read_byte(0xA1FF), 0xFC);
+    }
+
+    #[test]
+    fn mbc3_rom_ram_bank() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x13, 0x06, 0x03)).unwrap();
+
+        memory.write_byte(0x2000, 0x7F);
+        assert_eq!(memory.read_byte(0x4000), 0x7F);
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 0x01);
+
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0x4000, 0x03);
+        memory.write_byte(0xA000, 0x12);
+        memory.write_byte(0x4000, 0x00);
+        assert_eq!(memory.read_byte(0xA000), 0x00);
+        memory.write_byte(0x4000, 0x03);
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+    }
+
+    #[test]
+    fn mbc3_rtc() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        memory.load_cartidge(banked_rom(0x10, 0x01, 0x03)).unwrap();
+
+        memory.write_byte(0x0000, 0x0A);
+        // set clock to 23:59:58
+        memory.write_byte(0x4000, 0x0A);
+        memory.write_byte(0xA000, 23);
+        memory.write_byte(0x4000, 0x09);
+        memory.write_byte(0xA000, 59);
+        memory.write_byte(0x4000, 0x08);
+        memory.write_byte(0xA000, 58);
+
+        // 3 seconds of machine cycles
+        for _ in 0..(3 * 1048576 / 128) {
+            clock.tick(128, &mut memory);
+        }
+
+        // registers only change when latched
+        assert_eq!(memory.read_byte(0xA000), 58);
+        memory.write_byte(0x6000, 0x00);
+        memory.write_byte(0x6000, 0x01);
+        assert_eq!(memory.read_byte(0xA000), 1);
+        memory.write_byte(0x4000, 0x0A);
+        assert_eq!(memory.read_byte(0xA000), 0);
+        memory.write_byte(0x4000, 0x0B);
+        assert_eq!(memory.read_byte(0xA000), 1);
+
+        // save data round trip
+        let rtc_data = memory.get_rtc_data().unwrap();
+        assert_eq!(rtc_data.len(), 48);
+        let mut loaded = Memory::new();
+        loaded.load_cartidge(banked_rom(0x10, 0x01, 0x03)).unwrap();
+        loaded.set_rtc_data(&rtc_data);
+        assert_eq!(loaded.get_rtc_data().unwrap()[..40], rtc_data[..40]);
+    }
+
+    #[test]
+    fn mbc5_rom_bank() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x19, 0x08, 0x00)).unwrap();
+
+        // bank 0 is selectable in the switchable window
+        memory.write_byte(0x2000, 0x00);
+        assert_eq!(memory.read_byte(0x4000), 0);
+        memory.write_byte(0x2000, 0x34);
+        memory.write_byte(0x3000, 0x01);
+        assert_eq!(memory.read_byte(0x4000), 0x34);
+        assert_eq!(memory.read_byte(0x0000), 0);
+    }
+
+    #[test]
+    fn mbc5_ram_rumble() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x1E, 0x01, 0x03)).unwrap();
+
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0x4000, 0x0B);
+        assert!(memory.get_rumble());
+        memory.write_byte(0xA000, 0x12);
+        memory.write_byte(0x4000, 0x03);
+        assert!(!memory.get_rumble());
+        assert_eq!(memory.read_byte(0xA000), 0x12);
+    }
+
+    #[test]
+    fn battery_save_data() {
+        let mut memory = Memory::new();
+        memory.load_cartidge(banked_rom(0x03, 0x01, 0x03)).unwrap();
+        assert!(memory.has_battery());
+
+        memory.write_byte(0x0000, 0x0A);
+        memory.write_byte(0x6000, 0x01);
+        memory.write_byte(0x4000, 0x01);
+        memory.write_byte(0xA001, 0x12);
+        assert!(memory.take_ram_dirty());
+        assert!(!memory.take_ram_dirty());
+
+        // raw sram layout, banks in order
+        let save_data = memory.get_save_data();
+        assert_eq!(save_data.len(), 4 * 0x2000);
+        assert_eq!(save_data[0x2001], 0x12);
+
+        let mut loaded = Memory::new();
+        loaded.load_cartidge(banked_rom(0x03, 0x01, 0x03)).unwrap();
+        loaded.load_save_data(&save_data);
+        loaded.write_byte(0x0000, 0x0A);
+        loaded.write_byte(0x6000, 0x01);
+        loaded.write_byte(0x4000, 0x01);
+        assert_eq!(loaded.read_byte(0xA001), 0x12);
+    }
+
+    #[test]
+    fn cheat_decode() {
+        assert_eq!(
+            "AF1-50F".parse(),
+            Ok(Cheat::GameGenie {
+                address: 0x0150,
+                data: 0xAF,
+                compare: None,
+            })
+        );
+        assert_eq!(
+            "af1-50f-e25".parse(),
+            Ok(Cheat::GameGenie {
+                address: 0x0150,
+                data: 0xAF,
+                compare: Some(0xC3),
+            })
+        );
+        assert_eq!(
+            "01FF34C1".parse(),
+            Ok(Cheat::GameShark {
+                bank: None,
+                address: 0xC134,
+                data: 0xFF,
+            })
+        );
+        assert_eq!(
+            "810500A0".parse(),
+            Ok(Cheat::GameShark {
+                bank: Some(1),
+                address: 0xA000,
+                data: 0x05,
+            })
+        );
+        assert_eq!(
+            "AF1-507".parse::<Cheat>(),
+            Err(CheatError::InvalidAddress(0x8150))
+        );
+        assert_eq!(
+            "01FF3401".parse::<Cheat>(),
+            Err(CheatError::InvalidAddress(0x0134))
+        );
+        assert!(matches!(
+            "XYZ-123".parse::<Cheat>(),
+            Err(CheatError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn cheat_frames() {
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+        memory.load_cartidge(banked_rom(0x00, 0x00, 0x00)).unwrap();
+        memory.write_byte(0xFF40, 0x80);
+        // work ram, HRAM, the OAM DMA register and IE
+        memory
+            .cheats_mut()
+            .load("01FF34C1\n01AB80FF\n01C046FF\n011FFFFF\n")
+            .unwrap();
+        let dma = memory.read_byte(0xFF46);
+
+        // the PPU applies the codes at the start of vblank, with or without graphics
+        for _ in 1..114 * 144 {
+            clock.tick(1, &mut memory);
+        }
+        assert_eq!(memory.read_byte(0xC134), 0x00);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(0xC134), 0xFF);
+        assert_eq!(memory.read_byte(0xFF80), 0xAB);
+
+        // I/O registers and IE are left alone, so no DMA starts and blocks work ram
+        assert_eq!(memory.read_byte(0xFF46), dma);
+        assert_eq!(memory.read_byte(0xFFFF), 0x00);
+        clock.tick(2, &mut memory);
+        assert_eq!(memory.read_byte(0xC134), 0xFF);
+
+        // there is no vblank while the lcd is off
+        memory.write_byte(0xFF40, 0x00);
+        memory.write_byte(0xC134, 0x00);
+        for _ in 0..114 * 154 {
+            clock.tick(1, &mut memory);
+        }
+        assert_eq!(memory.read_byte(0xC134), 0x00);
+    }
+
+    #[test]
+    fn cheat_memory() {
+        let mut memory = Memory::new();
+        let mut rom = banked_rom(0x03, 0x01, 0x03);
+        rom[0x0150] = 0xC3;
+        rom[0x0151] = 0x12;
+        memory.load_cartidge(rom).unwrap();
+
+        let cheats = memory.cheats_mut();
+        cheats
+            .load("# infinite lives\nAF1-50F-E25 jump\n\n01FF34C1 health\n")
+            .unwrap();
+        // compare byte does not match
+        cheats.add("551-51F-E25").unwrap();
+        assert_eq!(memory.cheats().entries().len(), 3);
+        assert_eq!(memory.read_byte(0x0150), 0xAF);
+        assert_eq!(memory.read_byte(0x0151), 0x12);
+
+        // ram pokes wait for the frame boundary
+        assert_eq!(memory.read_byte(0xC134), 0x00);
+        memory.apply_cheats();
+        assert_eq!(memory.read_byte(0xC134), 0xFF);
+
+        memory.cheats_mut().set_enabled(0, false);
+        assert_eq!(memory.read_byte(0x0150), 0xC3);
+        memory.cheats_mut().set_enabled(0, true);
+        memory.cheats_mut().toggle();
+        assert_eq!(memory.read_byte(0x0150), 0xC3);
+        memory.write_byte(0xC134, 0x00);
+        memory.apply_cheats();
+        assert_eq!(memory.read_byte(0xC134), 0x00);
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn patch_detect() {
+        assert_eq!(PatchFormat::detect(b"PATCHEOF"), Some(PatchFormat::IPS));
+        assert_eq!(PatchFormat::detect(b"UPS1"), Some(PatchFormat::UPS));
+        assert_eq!(PatchFormat::detect(b"BPS1"), Some(PatchFormat::BPS));
+        assert_eq!(PatchFormat::detect(b"GBS"), None);
+        assert_eq!(apply_patch(&[0; 4], b"GBS"), Err(PatchError::UnknownFormat));
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn patch_ips() {
+        let rom = [0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        // normal record
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        // run length encoded record past the end of the rom
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(
+            apply_patch(&rom, &patch).unwrap(),
+            [0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC, 0xCC]
+        );
+
+        // truncation extension
+        patch.extend_from_slice(&[0x00, 0x00, 0x03]);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), [0x00, 0xAA, 0xBB]);
+
+        assert_eq!(
+            apply_patch(&rom, b"PATCH\x00\x00\x01\x00\x02\xAA"),
+            Err(PatchError::Truncated)
+        );
+    }
+
+    #[test]
+    fn patch_ups() {
+        let rom = [0, 1, 2, 3, 4, 5, 6, 7];
+        let target = [0, 1, 0xAA, 0xBB, 4, 5, 6, 7];
+        let patch = b"UPS1\x88\x88\x82\xA8\xB8\x00".to_vec();
+        let patch = with_footer(patch, &rom, &target);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+
+        // wrong rom
+        assert_eq!(
+            apply_patch(&target, &patch),
+            Err(PatchError::SourceChecksum {
+                expected: crc32(&rom),
+                actual: crc32(&target),
+            })
+        );
+
+        // corrupted patch
+        let mut corrupted = patch.clone();
+        corrupted[7] ^= 0xFF;
+        assert!(matches!(
+            apply_patch(&rom, &corrupted),
+            Err(PatchError::PatchChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn patch_bps() {
+        let rom = [0, 1, 2, 3, 4, 5, 6, 7];
+        let target = [0, 1, 2, 3, 0xAA, 0xBB, 6, 7, 0];
+        let mut patch = b"BPS1\x88\x89\x80".to_vec();
+        // source read 4
+        patch.push(0x8C);
+        // target read 2
+        patch.extend_from_slice(&[0x85, 0xAA, 0xBB]);
+        // source copy 2 from 6
+        patch.extend_from_slice(&[0x86, 0x8C]);
+        // target copy 1 from 0
+        patch.extend_from_slice(&[0x83, 0x80]);
+        let patch = with_footer(patch, &rom, &target);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+
+        let wrong_target = with_footer(patch[..patch.len() - 12].to_vec(), &rom, &rom);
+        assert!(matches!(
+            apply_patch(&rom, &wrong_target),
+            Err(PatchError::TargetChecksum { .. })
+        ));
+    }
+
+    #[test]
+    fn disasm_format() {
+        let cases = [
+            (Instruction::LD_R_R(Register::B, Register::C), "ld b, c"),
+            (Instruction::LD_R_N(Register::A, 0x3F), "ld a, $3F"),
+            (Instruction::LD_HL_R(Register::E), "ld [hl], e"),
+            (Instruction::LD_A_HL_I, "ld a, [hl+]"),
+            (Instruction::LDH_N_A(0x44), "ldh [$FF44], a"),
+            (
+                Instruction::LD_RR_NN(Register16::SP, 0xFFFE),
+                "ld sp, $FFFE",
+            ),
+            (Instruction::LD_HL_SP(-2), "ld hl, sp - 2"),
+            (Instruction::ADD_SP_E(5), "add sp, 5"),
+            (Instruction::ADC_HL, "adc a, [hl]"),
+            (Instruction::XOR_R(Register::A), "xor a"),
+            (Instruction::PUSH(Register16::AF), "push af"),
+            (Instruction::BIT_HL(7), "bit 7, [hl]"),
+            (Instruction::SWAP(Register::H), "swap h"),
+            (Instruction::JR_CC(Condition::NonZero, -2), "jr nz, $0150"),
+            (Instruction::JR(0x10), "jr $0162"),
+            (
+                Instruction::CALL_CC(Condition::Carry, 0x4000),
+                "call c, $4000",
+            ),
+            (Instruction::RET_CC(Condition::NotCarry), "ret nc"),
+            (Instruction::RST(0x38), "rst $38"),
+            (Instruction::Illegal(0xD3), "db $D3"),
+        ];
+        for (instruction, text) in cases {
+            assert_eq!(format_instruction(instruction, 0x150), text);
+        }
+    }
+
+    #[test]
+    fn disasm_range() {
+        let mut memory = Memory::new();
+        // ld b, 3; jr nz, -4; halt; jp $0150
+        memory.write_test(vec![0x06, 0x03, 0x20, 0xFC, 0x76, 0xC3, 0x50, 0x01]);
+
+        let lines = disassemble(&memory, 0..8);
+        let text: Vec<String> = lines.iter().map(|line| line.text()).collect();
+        assert_eq!(text, ["ld b, $03", "jr nz, $0000", "halt", "jp $0150"]);
+        assert_eq!(lines[3].address, 5);
+        assert_eq!(lines[3].bytes, [0xC3, 0x50, 0x01]);
+        assert_eq!(lines[1].to_string(), "0002  20 FC     jr nz, $0000");
+
+        // an instruction cut off by the end of the range is data
+        let lines = disassemble(&memory, 5..7);
+        assert_eq!(lines[0].text(), "db $C3");
+        assert_eq!(lines[1].text(), "ld d, b");
+
+        let line = disassemble_instruction(&memory, 5);
+        assert_eq!(line.to_string(), "0005  C3 50 01  jp $0150");
+    }
+
+    #[test]
+    fn disasm_bank() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x4000] = 0x18;
+        rom[0x4001] = 0xFE;
+
+        let lines = disassemble_bank(&rom, 1);
+        assert_eq!(lines[0].address, 0x4000);
+        assert_eq!(lines[0].text(), "jr $4000");
+        assert_eq!(lines.len(), 0x4000 - 1);
+        assert!(disassemble_bank(&rom, 2).is_empty());
+    }
+
+    #[test]
+    fn disasm_symbols() {
+        let symbols = parse_symbols(
+            "; comment\n[labels]\n00:0150 main\n02:4000 Foo.bar@1\n[definitions]\n00:ff80 hram\n",
+        );
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[&(0, 0x0150)], "main");
+        assert_eq!(symbols[&(2, 0x4000)], "Foo_bar_1");
+        assert_eq!(parse_symbols("01:4000 1up")[&(1, 0x4000)], "_1up");
+    }
+
+    #[test]
+    fn disasm_rom() {
+        let mut rom = vec![0xD3; 4 * 0x4000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // ld a, 2; ld [$2000], a; call $4000; jr @
+        rom[0x150..0x15A]
+            .copy_from_slice(&[0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x18, 0xFE]);
+        rom[0x8000] = 0xC9;
+
+        let disassembly = RomDisassembly::new(&rom, &Symbols::new());
+        assert_eq!(disassembly.bank_count(), 4);
+        assert!(disassembly.is_code(0, 0x150));
+        assert!(disassembly.is_code(2, 0x4000));
+        assert!(!disassembly.is_code(1, 0x4000));
+        assert!(!disassembly.is_code(0, 0x104));
+        assert_eq!(disassembly.label(2, 0x4000), Some("Call_002_4000"));
+        assert_eq!(disassembly.label(0, 0x151), None);
+
+        let asm = disassembly.to_string();
+        assert!(asm.contains("Boot:\n    nop\n    jp Jump_000_0150\n    db $D3"));
+        assert!(asm.contains("    call Call_002_4000\n"));
+        assert!(asm.contains("\nJump_000_0158:\n    jr Jump_000_0158\n"));
+        assert!(asm.contains(
+            "SECTION \"ROM Bank $002\", ROMX[$4000], BANK[$2]\n\nCall_002_4000:\n    ret\n"
+        ));
+
+        let symbols = parse_symbols("02:4000 Foo\n00:0158 Foo");
+        let asm = RomDisassembly::new(&rom, &symbols).to_string();
+        assert!(asm.contains("    call Foo_002_4000\n"));
+        assert!(asm.contains("    jr Foo\n"));
+    }
+
+    #[test]
+    fn disasm_reassemble() {
+        let mut rom = vec![0x00; 2 * 0x4000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        // ld a, 1; ld [$2000], a; call $4000; rst $38; jr @; db $D3
+        rom[0x150..0x15C].copy_from_slice(&[
+            0x3E, 0x01, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0xFF, 0x18, 0xFE, 0xD3,
+        ]);
+        // ld hl, $C000; ld [hl+], a; ret
+        rom[0x4000..0x4005].copy_from_slice(&[0x21, 0x00, 0xC0, 0x22, 0xC9]);
+        let mut roms = vec![(rom, String::new())];
+        for name in ["call_timing", "ei_sequence"] {
+            let path = format!("assets/mooneye_test_roms/acceptance/{}", name);
+            let rom = std::fs::read(format!("{}.gb", path)).unwrap();
+            let symbols = std::fs::read_to_string(format!("{}.sym", path)).unwrap();
+            roms.push((rom, symbols));
+        }
+
+        // the assembler has no sections, and the two banks of a 32 KiB rom are contiguous
+        for (rom, symbols) in roms {
+            let asm = RomDisassembly::new(&rom, &parse_symbols(&symbols)).to_string();
+            let source = asm
+                .lines()
+                .filter(|line| !line.starts_with("SECTION"))
+                .collect::<Vec<_>>()
+                .join("\n");
+            assert_eq!(assemble(&source), Ok(rom));
+        }
+    }
+
+    #[test]
+    fn asm_inverse() {
+        // every legal opcode with a few operands assembles back from its disassembly
+        for prefix in [None, Some(0xCB)] {
+            for opcode in 0..=0xFF {
+                for operand in [0x00, 0x7F, 0x80, 0xFF] {
+                    let bytes = match prefix {
+                        Some(prefix) => vec![prefix, opcode, operand],
+                        None => vec![opcode, operand, operand ^ 0x5A],
+                    };
+                    let sized =
+                        SizedInstruction::decode_with(|offset| bytes[offset as usize]).unwrap();
+                    if matches!(
+                        sized.instruction,
+                        Instruction::Illegal(_) | Instruction::STOP
+                    ) {
+                        continue;
+                    }
+                    let text = format_instruction(sized.instruction, 0x4000);
+                    assert_eq!(
+                        assemble_at(&text, 0x4000).as_deref(),
+                        Ok(&bytes[..sized.size as usize]),
+                        "{}",
+                        text
+                    );
+                }
+            }
+        }
+        assert_eq!(assemble("stop"), Ok(vec![0x10, 0x00]));
+    }
+
+    #[test]
+    fn asm_program() {
+        assert_eq!(assemble("ld b, 3\nhalt"), Ok(vec![0x06, 0x03, 0x76]));
+
+        let source = "
+            Start:
+                ld b, 3 ; counter
+            .loop: dec b
+                jr nz, .loop
+                LD HL, Data + 1
+                ldh a, [$FF44]
+                ld [hli], a
+                ld hl, sp-2
+                jp Start
+            Data: db $12, -1, %101, 2 * (3 + 4)
+                dw @, Data
+        ";
+        assert_eq!(
+            assemble_at(source, 0x150),
+            Ok(vec![
+                0x06, 0x03, 0x05, 0x20, 0xFD, 0x21, 0x61, 0x01, 0xF0, 0x44, 0x22, 0xF8, 0xFE, 0xC3,
+                0x50, 0x01, 0x12, 0xFF, 0x05, 0x0E, 0x64, 0x01, 0x60, 0x01
+            ])
+        );
+
+        assert_eq!(
+            assemble("nop\nld q, 1"),
+            Err(AsmError::InvalidInstruction(2, "ld q, 1".to_string()))
+        );
+        assert_eq!(
+            assemble("jp Nowhere"),
+            Err(AsmError::UnknownLabel(1, "Nowhere".to_string()))
+        );
+        assert_eq!(
+            assemble("a:\na:"),
+            Err(AsmError::DuplicateLabel(2, "a".to_string()))
+        );
+        assert_eq!(assemble("jr @ + 200"), Err(AsmError::OutOfRange(1, 198)));
+        assert_eq!(assemble("ld a, 256"), Err(AsmError::OutOfRange(1, 256)));
+        assert_eq!(
+            assemble("db 1 +"),
+            Err(AsmError::InvalidExpression(1, "1 +".to_string()))
+        );
+    }
+
     #[test]
     fn decode_ldrr() {
         let mut memory = Memory::new();
@@ -1495,6 +2533,81 @@ mod tests {
         )
     }
 
+    #[test]
+    fn decode_stop() {
+        let mut memory = Memory::new();
+
+        memory.write_test(vec![0x10, 0x00]);
+
+        let instr = SizedInstruction::decode(&memory, 0).unwrap();
+        assert_eq!(
+            instr,
+            SizedInstruction {
+                instruction: Instruction::STOP,
+                size: 2
+            }
+        )
+    }
+
+    #[test]
+    fn decode_illegal() {
+        for opcode in [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ] {
+            let instr = SizedInstruction::decode_with(|_| opcode).unwrap();
+            assert_eq!(
+                instr,
+                SizedInstruction {
+                    instruction: Instruction::Illegal(opcode),
+                    size: 1
+                }
+            )
+        }
+
+        // every opcode decodes to something
+        for opcode in 0..=0xFF {
+            assert!(SizedInstruction::decode_with(|_| opcode).is_some());
+        }
+    }
+
+    #[test]
+    fn decode_tables() {
+        for opcode in 0..=0xFF {
+            for operands in [[0x00, 0x00], [0x01, 0x80], [0x34, 0x12], [0xFF, 0x7F]] {
+                let fetch = |offset: Address| match offset {
+                    0 => opcode,
+                    offset => operands[offset as usize - 1],
+                };
+                let expected = SizedInstruction::decode_pattern(fetch);
+                assert_eq!(
+                    SizedInstruction::decode_with(fetch),
+                    expected,
+                    "{:#04X} {:02X?}",
+                    opcode,
+                    operands
+                );
+                assert_eq!(
+                    SizedInstruction::size_of(opcode),
+                    expected.map(|sized| sized.size)
+                );
+            }
+        }
+
+        for opcode in 0..=0xFF {
+            let fetch = |offset: Address| match offset {
+                0 => CB_PREFIX,
+                1 => opcode,
+                _ => 0xFF,
+            };
+            assert_eq!(
+                SizedInstruction::decode_with(fetch),
+                SizedInstruction::decode_pattern(fetch),
+                "CB {:#04X}",
+                opcode
+            );
+        }
+    }
+
     #[test]
     fn execute_addr() {
         let mut cpu = CPU::new();
@@ -1727,6 +2840,440 @@ mod tests {
         assert_eq!(cpu.b, 0xCA);
     }
 
+    #[test]
+    fn execute_halt_bug() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        // halt; inc a; inc b
+        memory.write_test(vec![0x76, 0x3C, 0x04]);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG);
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, TIMER_FLAG);
+
+        // ime is off with a pending interrupt, so the cpu does not halt
+        cpu.execute(&mut memory, &mut clock);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.pc, 1);
+
+        // inc a is executed twice, since pc fails to increment once
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(cpu.pc, 1);
+        cpu.execute(&mut memory, &mut clock);
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(cpu.a, 2);
+        assert_eq!(cpu.b, 1);
+        assert_eq!(cpu.pc, 3);
+    }
+
+    #[test]
+    fn execute_halt_wake() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        memory.write_test(vec![0x76]);
+        cpu.ime = (None, true);
+        cpu.execute(&mut memory, &mut clock);
+        assert!(cpu.halt);
+
+        // ime alone does not wake the cpu, neither does a disabled interrupt
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert!(cpu.halt);
+        cpu.ime = (None, false);
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, TIMER_FLAG);
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert!(cpu.halt);
+
+        // with ime off the cpu wakes up without servicing the interrupt
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG);
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.pc, 1);
+    }
+
+    #[test]
+    fn interrupt_dispatch() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        cpu.pc = 0x1234;
+        cpu.sp = 0xD000;
+        cpu.ime = (None, true);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG | SERIAL_FLAG);
+        memory.write_byte(
+            INTERRUPT_FLAG_ADDRESS,
+            VBLANK_FLAG | TIMER_FLAG | SERIAL_FLAG,
+        );
+
+        // the highest priority enabled interrupt is serviced in 5 m-cycles
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert_eq!(cpu.pc, 0x50);
+        assert_eq!(cpu.sp, 0xCFFE);
+        assert_eq!(memory.read_byte(0xCFFF), 0x12);
+        assert_eq!(memory.read_byte(0xCFFE), 0x34);
+        assert_eq!(clock.get_timestamp(), 5);
+        assert!(!cpu.ime.1);
+
+        // only the serviced bit is cleared, disabled requests stay pending
+        assert_eq!(
+            memory.read_byte(INTERRUPT_FLAG_ADDRESS) & 0x1F,
+            VBLANK_FLAG | SERIAL_FLAG
+        );
+    }
+
+    #[test]
+    fn interrupt_ie_push() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        // pushing the high byte of pc overwrites IE and cancels the interrupt
+        cpu.pc = 0x0234;
+        cpu.sp = 0x0000;
+        cpu.ime = (None, true);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG);
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, TIMER_FLAG);
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(memory.read_byte(INTERRUPT_ENABLE_ADDRESS), 0x02);
+        assert_eq!(memory.read_byte(INTERRUPT_FLAG_ADDRESS) & 0x1F, TIMER_FLAG);
+    }
+
+    #[test]
+    fn ei_delay() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        // ei; inc a; inc a
+        memory.write_test(vec![0xFB, 0x3C, 0x3C]);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG);
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, TIMER_FLAG);
+        cpu.sp = 0xD000;
+
+        // the interrupt is serviced after the instruction following ei
+        for _ in 0..2 {
+            cpu.execute(&mut memory, &mut clock);
+            cpu.ime_step();
+            cpu.handle_interrupts(&mut memory, &mut clock);
+        }
+        assert_eq!(cpu.a, 1);
+        assert_eq!(cpu.pc, 0x50);
+    }
+
+    #[test]
+    fn execute_illegal() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        memory.write_test(vec![0xD3]);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, TIMER_FLAG);
+        memory.write_byte(INTERRUPT_FLAG_ADDRESS, TIMER_FLAG);
+        cpu.sp = 0xD000;
+        cpu.ime = (None, true);
+
+        // the cpu locks up on the opcode and ignores interrupts
+        cpu.execute(&mut memory, &mut clock);
+        assert!(cpu.locked);
+        assert_eq!(cpu.pc, 0);
+        cpu.handle_interrupts(&mut memory, &mut clock);
+        assert_eq!(cpu.pc, 0);
+        assert_eq!(cpu.sp, 0xD000);
+    }
+
+    /// One machine cycle of bus activity, as logged by the flat test bus
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum BusCycle {
+        /// Internal cycle without a bus access
+        Idle,
+        Read(Address, Byte),
+        Write(Address, Byte),
+    }
+
+    /// Plain 64KiB of ram without cartridge, echo or I/O mapping, which logs every bus cycle.
+    /// The clock is never ticked, so the timer and other components see no registers
+    struct FlatBus {
+        memory: Vec<Byte>,
+        cycles: Vec<BusCycle>,
+    }
+
+    impl FlatBus {
+        fn new() -> Self {
+            FlatBus {
+                memory: vec![0; 0x10000],
+                cycles: Vec::new(),
+            }
+        }
+
+        /// Bus cycles logged since the last call
+        fn take_bus_cycles(&mut self) -> Vec<BusCycle> {
+            std::mem::take(&mut self.cycles)
+        }
+    }
+
+    impl Bus for FlatBus {
+        fn read_byte(&self, address: Address) -> Byte {
+            self.memory[address as usize]
+        }
+
+        fn write_byte(&mut self, address: Address, byte: Byte) {
+            self.memory[address as usize] = byte;
+        }
+
+        fn tick(&mut self, _clock: &mut Clock, mcycles: u8) {
+            self.cycles
+                .extend(std::iter::repeat_n(BusCycle::Idle, mcycles as usize));
+        }
+
+        fn read_cycle(&mut self, _clock: &mut Clock, address: Address) -> Byte {
+            let byte = self.read_byte(address);
+            self.cycles.push(BusCycle::Read(address, byte));
+            byte
+        }
+
+        fn write_cycle(&mut self, _clock: &mut Clock, address: Address, byte: Byte) {
+            self.cycles.push(BusCycle::Write(address, byte));
+            self.write_byte(address, byte);
+        }
+    }
+
+    #[test]
+    fn flat_memory_bus_log() {
+        let mut cpu = CPU::new();
+        let mut memory = FlatBus::new();
+        let mut clock = Clock::new();
+
+        // ld [hl], $AB; push bc, with hl pointing at an I/O register
+        memory.memory[..3].copy_from_slice(&[0x36, 0xAB, 0xC5]);
+        cpu.h = 0xFF;
+        cpu.l = 0x04;
+        cpu.b = 0x12;
+        cpu.c = 0x34;
+        cpu.sp = 0xD000;
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(
+            memory.take_bus_cycles(),
+            vec![
+                BusCycle::Read(0x0000, 0x36),
+                BusCycle::Read(0x0001, 0xAB),
+                BusCycle::Write(0xFF04, 0xAB)
+            ]
+        );
+        cpu.execute(&mut memory, &mut clock);
+        assert_eq!(
+            memory.take_bus_cycles(),
+            vec![
+                BusCycle::Read(0x0002, 0xC5),
+                BusCycle::Idle,
+                BusCycle::Write(0xCFFF, 0x12),
+                BusCycle::Write(0xCFFE, 0x34)
+            ]
+        );
+        // the timer does not see the write to DIV
+        assert_eq!(memory.read_byte(0xFF04), 0xAB);
+    }
+
+    /// Runs the SM83 SingleStepTests (github.com/SingleStepTests/sm83) in the SM83_TESTS
+    /// directory, which CI checks out: `SM83_TESTS=sm83/v1 cargo test --release sm83 -- --nocapture`
+    #[test]
+    fn sm83_single_step() {
+        let Ok(dir) = std::env::var("SM83_TESTS") else {
+            println!("SM83_TESTS is not set, skipping the SingleStepTests");
+            return;
+        };
+        let (failed, total) = sm83_run_dir(&dir);
+        assert!(
+            failed.is_empty(),
+            "{} of {} opcodes failed: {}",
+            failed.len(),
+            total,
+            failed.join(", ")
+        );
+    }
+
+    #[test]
+    fn sm83_fixtures() {
+        // hand written vectors in the SingleStepTests format
+        let (failed, total) = sm83_run_dir("assets/sm83/fixtures");
+        assert_eq!(total, 6);
+        assert!(failed.is_empty(), "failed: {}", failed.join(", "));
+
+        // a wrong cycle is reported
+        let contents = std::fs::read_to_string("assets/sm83/fixtures/c5.json").unwrap();
+        let mut vectors: Value = serde_json::from_str(&contents).unwrap();
+        vectors[0]["cycles"][1][1] = Value::from(0x13);
+        let failure = sm83_run(&vectors[0]).unwrap_err();
+        assert!(failure.contains("cycles"), "{}", failure);
+    }
+
+    /// Run the vector files in dir, printing pass or fail for every opcode.
+    /// Returns the failed opcodes and the number of opcodes
+    fn sm83_run_dir(dir: &str) -> (Vec<String>, usize) {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap_or_else(|e| panic!("Unable to read {}: {}", dir, e))
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        assert!(!files.is_empty(), "No test vectors in {}", dir);
+
+        let mut failed = Vec::new();
+        for path in &files {
+            let contents = std::fs::read_to_string(path).unwrap();
+            let vectors: Value = serde_json::from_str(&contents).unwrap();
+            let vectors = vectors.as_array().unwrap();
+            let failures = vectors
+                .iter()
+                .filter_map(|vector| sm83_run(vector).err())
+                .collect::<Vec<_>>();
+            let opcode = path.file_stem().unwrap().to_string_lossy().to_string();
+            println!(
+                "{:6} {} {}/{}",
+                opcode,
+                if failures.is_empty() { "pass" } else { "FAIL" },
+                vectors.len() - failures.len(),
+                vectors.len()
+            );
+            if let Some(failure) = failures.first() {
+                println!("       {}", failure);
+                failed.push(opcode);
+            }
+        }
+        (failed, files.len())
+    }
+
+    /// Run one test vector against flat memory, returning how the result differs from it
+    fn sm83_run(vector: &Value) -> Result<(), String> {
+        let value = |state: &Value, key: &str| state[key].as_u64().unwrap_or_default();
+        let initial = &vector["initial"];
+        let mut cpu = CPU::new();
+        let mut memory = FlatBus::new();
+        let mut clock = Clock::new();
+        cpu.a = value(initial, "a") as Byte;
+        cpu.f = value(initial, "f") as Byte;
+        cpu.b = value(initial, "b") as Byte;
+        cpu.c = value(initial, "c") as Byte;
+        cpu.d = value(initial, "d") as Byte;
+        cpu.e = value(initial, "e") as Byte;
+        cpu.h = value(initial, "h") as Byte;
+        cpu.l = value(initial, "l") as Byte;
+        cpu.sp = value(initial, "sp") as Word;
+        cpu.pc = value(initial, "pc") as Word;
+        cpu.ime = (None, value(initial, "ime") == 1);
+        memory.write_byte(INTERRUPT_ENABLE_ADDRESS, value(initial, "ie") as Byte);
+        for entry in initial["ram"].as_array().into_iter().flatten() {
+            memory.write_byte(
+                entry[0].as_u64().unwrap() as Address,
+                entry[1].as_u64().unwrap() as Byte,
+            );
+        }
+        memory.take_bus_cycles();
+
+        // cycles are [address, data, "r-m"] for reads, "-wm" for writes, and "---" when idle
+        let expected_cycles = vector["cycles"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|cycle| {
+                let address = cycle[0].as_u64().unwrap_or_default() as Address;
+                let byte = cycle[1].as_u64().unwrap_or_default() as Byte;
+                match cycle[2].as_str().unwrap_or_default() {
+                    pins if pins.starts_with('r') => BusCycle::Read(address, byte),
+                    pins if pins.contains('w') => BusCycle::Write(address, byte),
+                    _ => BusCycle::Idle,
+                }
+            })
+            .collect::<Vec<_>>();
+        // fetch and execute overlap: the opcode at pc - 1 was fetched by the previous
+        // instruction, and the last cycle fetches the next opcode at pc
+        cpu.pc = cpu.pc.wrapping_sub(1);
+        cpu.execute(&mut memory, &mut clock);
+        cpu.ime_step();
+        memory.read_cycle(&mut clock, cpu.pc);
+        cpu.pc = cpu.pc.wrapping_add(1);
+        let mut cycles = memory.take_bus_cycles();
+        cycles.remove(0);
+
+        let expected = &vector["final"];
+        let mut differences = Vec::new();
+        let registers = [
+            ("a", cpu.a as u64),
+            ("f", cpu.f as u64),
+            ("b", cpu.b as u64),
+            ("c", cpu.c as u64),
+            ("d", cpu.d as u64),
+            ("e", cpu.e as u64),
+            ("h", cpu.h as u64),
+            ("l", cpu.l as u64),
+            ("sp", cpu.sp as u64),
+            ("pc", cpu.pc as u64),
+        ];
+        for (name, actual) in registers {
+            if actual != value(expected, name) {
+                differences.push(format!(
+                    "{} {:#X} != {:#X}",
+                    name,
+                    actual,
+                    value(expected, name)
+                ));
+            }
+        }
+        if expected["ime"].is_u64() && cpu.ime.1 != (value(expected, "ime") == 1) {
+            differences.push(format!("ime {} != {}", cpu.ime.1, expected["ime"]));
+        }
+        for entry in expected["ram"].as_array().into_iter().flatten() {
+            let address = entry[0].as_u64().unwrap() as Address;
+            let byte = entry[1].as_u64().unwrap() as Byte;
+            let actual = memory.read_byte(address);
+            if actual != byte {
+                differences.push(format!(
+                    "[{:#06X}] {:#04X} != {:#04X}",
+                    address, actual, byte
+                ));
+            }
+        }
+
+        if cycles != expected_cycles {
+            differences.push(format!("cycles {:X?} != {:X?}", cycles, expected_cycles));
+        }
+
+        if differences.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{}: {}", vector["name"], differences.join(", ")))
+        }
+    }
+
+    #[test]
+    fn execute_stop() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let mut clock = Clock::new();
+
+        // stop swallows the next byte
+        memory.write_test(vec![0x10, 0x3C]);
+        clock.tick(100, &mut memory);
+        cpu.execute(&mut memory, &mut clock);
+        assert!(cpu.stop);
+        assert_eq!(cpu.pc, 2);
+        clock.tick(1, &mut memory);
+        assert_eq!(memory.read_byte(Clock::DIV_ADDRESS), 0);
+
+        // only a button of the selected line ends stop mode
+        memory.write_io(JOYPAD_REGISTER_ADDRESS, BUTTONS_FLAG | 0x0F);
+        cpu.wake_from_stop(&memory);
+        assert!(cpu.stop);
+        memory.write_io(JOYPAD_REGISTER_ADDRESS, RIGHT_BUTTON & !DPAD_FLAG);
+        cpu.wake_from_stop(&memory);
+        assert!(!cpu.stop);
+    }
+
     #[test]
     fn joypad_test_up() {
         let mut memory = Memory::new();